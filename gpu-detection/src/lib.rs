@@ -1,8 +1,9 @@
-use model::{Clocks, Cuda, Gpu, Memory};
+use model::{Clocks, Cuda, Gpu, GpuUsage, Memory, Pcie, Power};
 use nvml_wrapper::error::NvmlError;
 use nvml_wrapper::{enum_wrappers::device::Clock, Device, Nvml};
 use thiserror::Error;
 
+mod memory;
 pub mod model;
 
 #[derive(Error, Debug)]
@@ -35,17 +36,33 @@ impl GpuDetection {
 
     /// `uuid` of GPU device. If not provided first available GPU device will be used.
     pub fn detect<S: AsRef<str>>(&self, uuid: Option<S>) -> Result<Gpu, GpuDetectionError> {
+        let dev = self.device(uuid)?;
+        self.device_info(dev)
+            .map_err(|err| GpuDetectionError::GpuInfoAccessError(err.to_string()))
+    }
+
+    /// `uuid` of GPU device selected the same way as in `detect`.
+    pub fn uuid<S: AsRef<str>>(&self, uuid: Option<S>) -> Result<String, GpuDetectionError> {
+        let dev = self.device(uuid)?;
+        dev.uuid()
+            .map_err(|err| GpuDetectionError::GpuInfoAccessError(err.to_string()))
+    }
+
+    /// Current usage of GPU device. `uuid` selects the device the same way as in `detect`.
+    pub fn usage<S: AsRef<str>>(&self, uuid: Option<S>) -> Result<GpuUsage, GpuDetectionError> {
+        let dev = self.device(uuid)?;
+        device_usage(&dev).map_err(|err| GpuDetectionError::GpuInfoAccessError(err.to_string()))
+    }
+
+    fn device<S: AsRef<str>>(&self, uuid: Option<S>) -> Result<Device, GpuDetectionError> {
         if let Some(uuid) = uuid {
-            let dev = self.nvml.device_by_uuid(uuid.as_ref()).map_err(|err| {
+            return self.nvml.device_by_uuid(uuid.as_ref()).map_err(|err| {
                 GpuDetectionError::GpuAccessError(format!(
                     "Failed to get GPU device with UUID: {}. Err {}",
                     uuid.as_ref(),
                     err
                 ))
-            })?;
-            return self
-                .device_info(dev)
-                .map_err(|err| GpuDetectionError::GpuInfoAccessError(err.to_string()));
+            });
         };
 
         let gpu_count = self.nvml.device_count().map_err(|err| {
@@ -57,28 +74,33 @@ impl GpuDetection {
         }
 
         let index = 0;
-        let dev = self.nvml.device_by_index(index).map_err(|err| {
+        self.nvml.device_by_index(index).map_err(|err| {
             GpuDetectionError::GpuAccessError(format!(
                 "Failed to get GPU device under index: {}. Err {}",
                 index, err
             ))
-        })?;
-
-        self.device_info(dev)
-            .map_err(|err| GpuDetectionError::GpuInfoAccessError(err.to_string()))
+        })
     }
 
     fn device_info(&self, dev: Device) -> Result<Gpu, NvmlError> {
         let model = dev.name()?;
+        let architecture = unavailable_as_none(dev.architecture())?.map(|arch| arch.to_string());
+        let driver_version = self.nvml.sys_driver_version()?;
         let version = self.cuda_version()?;
         let cuda = cuda(&dev, version)?;
         let clocks = clocks(&dev)?;
-        let memory = memory(&dev)?;
+        let memory = memory(&dev, &model)?;
+        let pcie = pcie(&dev)?;
+        let power = power(&dev)?;
         Ok(Gpu {
             model,
+            architecture,
+            driver_version,
             cuda,
             clocks,
             memory,
+            pcie,
+            power,
         })
     }
 
@@ -120,28 +142,112 @@ fn clocks(dev: &Device) -> Result<Clocks, NvmlError> {
     })
 }
 
-fn memory(dev: &Device) -> Result<Memory, NvmlError> {
+fn memory(dev: &Device, model: &str) -> Result<Memory, NvmlError> {
     let total_bytes = dev.memory_info()?.total;
     let total_gib = bytes_to_gib(total_bytes);
+    let bandwidth_gib = match memory::is_known_model(model) {
+        true => bandwidth_gib(dev)?,
+        // Data rate of unknown memory type may differ.
+        false => None,
+    };
+    let ecc_enabled = not_supported_as_none(dev.is_ecc_enabled())?
+        .map(|ecc| ecc.currently_enabled)
+        .unwrap_or(false);
     Ok(Memory {
-        bandwidth_gib: None,
+        bandwidth_gib,
         total_gib,
+        ecc_enabled,
     })
 }
 
-/// Unused because of lack of `memTransferRatemax` property.
-#[allow(dead_code)]
-fn bandwidth_gib(dev: &Device) -> Result<u32, NvmlError> {
-    let memory_bus_width = dev.memory_bus_width()?;
-    let supported_memory_clocks = dev.supported_memory_clocks()?;
+/// Bandwidth is not published when memory bus width or memory clocks are not supported by the device.
+fn bandwidth_gib(dev: &Device) -> Result<Option<u32>, NvmlError> {
+    let Some(memory_bus_width) = not_supported_as_none(dev.memory_bus_width())? else {
+        return Ok(None);
+    };
+    let Some(supported_memory_clocks) = not_supported_as_none(dev.supported_memory_clocks())?
+    else {
+        return Ok(None);
+    };
     let max_memory_clock = supported_memory_clocks.iter().cloned().fold(0, u32::max);
     // `nvml` does not provide `memTransferRatemax` like `nvidia-settings` tool does.
-    // Transfer rate is a result of memory clock, bus width, and memory specific multiplier (for DDR it is 2)
-    let data_rate = 2; // value for DDR
-    let bandwidth_gib = max_memory_clock * memory_bus_width * data_rate / (1000 * 8);
-    Ok(bandwidth_gib)
+    // Transfer rate is a result of memory clock, bus width, and memory data rate.
+    Ok(Some(memory::bandwidth_gib(
+        memory_bus_width,
+        max_memory_clock,
+    )))
+}
+
+fn device_usage(dev: &Device) -> Result<GpuUsage, NvmlError> {
+    let utilization_percent = dev.utilization_rates()?.gpu;
+    let free_memory_gib = bytes_to_gib(dev.memory_info()?.free);
+    let graphics_processes = not_supported_as_none(dev.running_graphics_processes())?
+        .map(|processes| processes.len())
+        .unwrap_or(0);
+    Ok(GpuUsage {
+        utilization_percent,
+        free_memory_gib,
+        graphics_processes,
+    })
+}
+
+fn pcie(dev: &Device) -> Result<Pcie, NvmlError> {
+    let generation = unavailable_as_none(dev.max_pcie_link_gen())?;
+    let width = unavailable_as_none(dev.max_pcie_link_width())?;
+    Ok(Pcie { generation, width })
+}
+
+fn power(dev: &Device) -> Result<Power, NvmlError> {
+    let limit_mw = not_supported_as_none(dev.enforced_power_limit())?;
+    let limit_w = limit_mw.map(|limit_mw| limit_mw / 1000);
+    Ok(Power { limit_w })
+}
+
+/// Properties not supported by the device (e.g. ECC on consumer GPUs) are not failing detection.
+fn not_supported_as_none<T>(result: Result<T, NvmlError>) -> Result<Option<T>, NvmlError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(NvmlError::NotSupported) => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Properties not available on the device, unknown to the driver (e.g. on older drivers),
+/// or unknown to `nvml-wrapper` (e.g. architecture of newer GPUs) are not failing detection.
+fn unavailable_as_none<T>(result: Result<T, NvmlError>) -> Result<Option<T>, NvmlError> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(
+            NvmlError::NotSupported
+            | NvmlError::FunctionNotFound
+            | NvmlError::FailedToLoadSymbol(_)
+            | NvmlError::UnexpectedVariant(_),
+        ) => Ok(None),
+        Err(err) => Err(err),
+    }
 }
 
 fn bytes_to_gib(memory: u64) -> f32 {
     (memory as f64 / 1024.0 / 1024.0 / 1024.0) as f32
 }
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case(NvmlError::NotSupported; "not supported")]
+    #[test_case(NvmlError::FunctionNotFound; "function not found")]
+    #[test_case(NvmlError::FailedToLoadSymbol("nvmlDeviceGetArchitecture".into()); "old driver")]
+    #[test_case(NvmlError::UnexpectedVariant(10); "unknown architecture")]
+    fn unavailable_as_none_test(err: NvmlError) {
+        assert_eq!(unavailable_as_none::<u32>(Err(err)).unwrap(), None);
+    }
+
+    #[test]
+    fn unavailable_as_none_error_test() {
+        assert!(unavailable_as_none::<u32>(Err(NvmlError::GpuLost)).is_err());
+        assert_eq!(unavailable_as_none(Ok(4)).unwrap(), Some(4));
+    }
+}
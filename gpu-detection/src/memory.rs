@@ -0,0 +1,82 @@
+/// Model name fragments of GPUs with known memory (GDDR5, GDDR5X, GDDR6, GDDR6X, HBM2, HBM2e
+/// and HBM3).
+///
+/// NVML (as exposed by `nvml-wrapper`) does not report memory type, so it is resolved from
+/// GPU model name. The bandwidth formula is the same for every listed memory type
+/// (see [`DATA_RATE`]), so bandwidth is published only for listed models, as other memory types
+/// (e.g. GDDR7) are not known to match it.
+const KNOWN_MODELS: &[&str] = &[
+    // Datacenter
+    "H100",
+    "H200",
+    "A100",
+    "A30",
+    "V100",
+    "P100",
+    "TITAN V",
+    "A10",
+    "A40",
+    "L40",
+    "L4",
+    "T4",
+    // GeForce RTX 40 and 30
+    "RTX 40",
+    "RTX 30",
+    // GeForce RTX 20, GTX 16, Quadro RTX and RTX A series
+    "RTX 20",
+    "GTX 16",
+    "Quadro RTX",
+    "RTX A",
+    // GeForce GTX 10 and 9, TITAN X
+    "GTX 10",
+    "GTX 9",
+    "TITAN X",
+];
+
+/// Checks if GPU model has memory of a type matching the bandwidth formula.
+pub(crate) fn is_known_model(model: &str) -> bool {
+    KNOWN_MODELS.iter().any(|name| model.contains(name))
+}
+
+/// Transfers per memory clock cycle, relative to the memory clock reported by NVML.
+///
+/// For all known memory types NVML reports memory clock at half of the effective per pin data rate
+/// (e.g. 9751 MHz for 19.5 Gbps GDDR6X on RTX 3090 or 877 MHz for 1.75 Gbps HBM2 on V100).
+const DATA_RATE: u32 = 2;
+
+/// Memory bandwidth in GB/s.
+pub(crate) fn bandwidth_gib(bus_width: u32, max_memory_clock: u32) -> u32 {
+    max_memory_clock * bus_width * DATA_RATE / (1000 * 8)
+}
+
+#[cfg(test)]
+mod tests {
+    use test_case::test_case;
+
+    use super::*;
+
+    #[test_case("NVIDIA GeForce RTX 4090", true; "RTX 4090")]
+    #[test_case("NVIDIA GeForce RTX 4060 Ti", true; "RTX 4060 Ti")]
+    #[test_case("NVIDIA GeForce RTX 3070 Ti", true; "RTX 3070 Ti")]
+    #[test_case("NVIDIA GeForce RTX 2080 SUPER", true; "RTX 2080 SUPER")]
+    #[test_case("NVIDIA GeForce GTX 1080 Ti", true; "GTX 1080 Ti")]
+    #[test_case("NVIDIA GeForce GTX 1070", true; "GTX 1070")]
+    #[test_case("NVIDIA A100-SXM4-40GB", true; "A100")]
+    #[test_case("NVIDIA H100 PCIe", true; "H100 PCIe")]
+    #[test_case("Tesla V100-SXM2-16GB", true; "V100")]
+    #[test_case("NVIDIA GeForce RTX 5090", false; "RTX 5090")]
+    #[test_case("NVIDIA GeForce GT 710", false; "unknown")]
+    fn known_model_test(model: &str, expected: bool) {
+        assert_eq!(is_known_model(model), expected);
+    }
+
+    #[test_case(384, 9751, 936; "RTX 3090")]
+    #[test_case(384, 10501, 1008; "RTX 4090")]
+    #[test_case(256, 7001, 448; "RTX 2080")]
+    #[test_case(256, 5005, 320; "GTX 1080")]
+    #[test_case(4096, 877, 898; "V100")]
+    #[test_case(5120, 1215, 1555; "A100")]
+    fn bandwidth_test(bus_width: u32, clock: u32, expected: u32) {
+        assert_eq!(bandwidth_gib(bus_width, clock), expected);
+    }
+}
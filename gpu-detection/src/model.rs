@@ -4,9 +4,14 @@ use serde::Serialize;
 #[serde(rename_all = "kebab-case")]
 pub struct Gpu {
     pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub architecture: Option<String>,
+    pub driver_version: String,
     pub cuda: Cuda,
     pub clocks: Clocks,
     pub memory: Memory,
+    pub pcie: Pcie,
+    pub power: Power,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -39,4 +44,31 @@ pub struct Memory {
     pub bandwidth_gib: Option<u32>,
     #[serde(rename(serialize = "total.gib"))]
     pub total_gib: f32,
+    #[serde(rename(serialize = "ecc.enabled"))]
+    pub ecc_enabled: bool,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Pcie {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Power {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(rename(serialize = "limit.w"))]
+    pub limit_w: Option<u32>,
+}
+
+/// Current GPU usage. Not a part of the offer.
+#[derive(Clone, Debug)]
+pub struct GpuUsage {
+    pub utilization_percent: u32,
+    pub free_memory_gib: f32,
+    pub graphics_processes: usize,
 }
@@ -72,6 +72,14 @@ async fn main() -> anyhow::Result<()> {
 
     log::info!("Dummy runtime. Args: {args:?}");
 
+    let port = match args.iter().position(|arg| arg == "--port") {
+        Some(index) => args
+            .get(index + 1)
+            .ok_or_else(|| anyhow::anyhow!("Missing --port value"))?
+            .parse()?,
+        None => 7861,
+    };
+
     Ok(HttpServer::new(|| {
         App::new()
             .service(index)
@@ -79,7 +87,7 @@ async fn main() -> anyhow::Result<()> {
             .service(generate_traffic)
             .service(text2img)
     })
-    .bind(("127.0.0.1", 7861))?
+    .bind(("127.0.0.1", port))?
     .run()
     .await?)
 }
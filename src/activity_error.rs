@@ -0,0 +1,19 @@
+//! Error message of `Ready` activity.
+
+use std::sync::{Arc, Mutex};
+
+/// Error message kept by activity state updates, which otherwise would clear it.
+#[derive(Clone, Default)]
+pub(crate) struct ActivityError {
+    message: Arc<Mutex<Option<String>>>,
+}
+
+impl ActivityError {
+    pub fn get(&self) -> Option<String> {
+        self.message.lock().unwrap().clone()
+    }
+
+    pub fn set(&self, message: Option<String>) {
+        *self.message.lock().unwrap() = message;
+    }
+}
@@ -0,0 +1,214 @@
+//! Runtime performance benchmark published in the offer.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use gpu_detection::GpuDetection;
+
+use crate::process::{InferenceRequest, Runtime, RuntimeConfig};
+use crate::self_test::{send_inference, wait_for_api, with_runtime};
+
+pub(crate) const BENCHMARK_PROPERTY: &str = "golem.srv.comp.ai.benchmark";
+
+/// Standard workload of the runtime.
+pub(crate) struct Workload {
+    pub request: InferenceRequest,
+    /// Name of measured throughput, e.g. `images-per-sec`.
+    pub metric: String,
+    /// Number of results (e.g. images) generated by single request.
+    pub results_per_request: u32,
+}
+
+/// GPU used by the runtime. Results of other GPU are not published.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct BenchmarkGpu {
+    pub uuid: Option<String>,
+    pub model: Option<String>,
+}
+
+impl BenchmarkGpu {
+    pub fn detect<CONFIG: RuntimeConfig>(config: &CONFIG) -> Self {
+        let gpu_detection = match GpuDetection::init() {
+            Ok(gpu_detection) => gpu_detection,
+            Err(err) => {
+                log::debug!("Benchmark without GPU. Err {err}");
+                return Self::default();
+            }
+        };
+        Self {
+            uuid: gpu_detection.uuid(config.gpu_uuid()).ok(),
+            model: gpu_detection
+                .detect(config.gpu_uuid())
+                .ok()
+                .map(|gpu| gpu.model),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct BenchmarkResult {
+    pub runtime: String,
+    /// Results saved without GPU info are treated as results of unknown GPU.
+    #[serde(default)]
+    pub gpu: BenchmarkGpu,
+    pub metric: String,
+    pub score: f64,
+    pub workload: Value,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl BenchmarkResult {
+    pub fn load(path: &Path) -> anyhow::Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = fs::File::open(path)
+            .with_context(|| format!("Failed to open benchmark file {path:?}"))?;
+        let result = serde_json::from_reader(file)
+            .with_context(|| format!("Failed to parse benchmark file {path:?}"))?;
+        Ok(Some(result))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create benchmark dir {dir:?}"))?;
+        }
+        let file = fs::File::create(path)
+            .with_context(|| format!("Failed to create benchmark file {path:?}"))?;
+        Ok(serde_json::to_writer_pretty(file, self)?)
+    }
+
+    /// Value of `golem.srv.comp.ai.benchmark` offer property.
+    pub fn offer_property(&self) -> Value {
+        let mut property = json!({
+            "workload": self.workload,
+            "timestamp": self.timestamp,
+        });
+        property[&self.metric] = json!(self.score);
+        property
+    }
+}
+
+/// Benchmark results file in user data dir (exe-unit dir may be read-only), unless set explicitly.
+pub(crate) fn file_path(path: Option<&Path>, runtime: &str) -> anyhow::Result<PathBuf> {
+    if let Some(path) = path {
+        return Ok(path.to_path_buf());
+    }
+    let dirs = ProjectDirs::from("", "GolemFactory", "ya-runtime-ai")
+        .context("Unable to get user data dir")?;
+    Ok(dirs.data_dir().join(format!("{runtime}-benchmark.json")))
+}
+
+/// Value of `golem.srv.comp.ai.benchmark` offer property, if valid results of `runtime` run on `gpu`
+/// are saved in `path`. Unreadable results are skipped, so they do not block publishing the offer.
+pub(crate) fn offer_property(path: &Path, runtime: &str, gpu: &BenchmarkGpu) -> Option<Value> {
+    match BenchmarkResult::load(path) {
+        Ok(Some(benchmark)) if benchmark.runtime != runtime => {
+            log::warn!(
+                "Benchmark results of {} runtime ignored ({path:?})",
+                benchmark.runtime
+            );
+            None
+        }
+        Ok(Some(benchmark)) if &benchmark.gpu != gpu => {
+            log::warn!(
+                "Benchmark results of other GPU ({:?}) ignored ({path:?})",
+                benchmark.gpu
+            );
+            None
+        }
+        Ok(Some(benchmark)) => Some(benchmark.offer_property()),
+        Ok(None) => {
+            log::debug!("No benchmark results ({path:?})");
+            None
+        }
+        Err(err) => {
+            log::warn!("Benchmark results ignored. Err {err:#}");
+            None
+        }
+    }
+}
+
+/// Runs `requests` of runtime's standard workload, preceded by a warm up request.
+pub(crate) async fn run<RUNTIME: Runtime>(
+    runtime: &str,
+    config: &RUNTIME::CONFIG,
+    model: Option<PathBuf>,
+    requests: u32,
+) -> anyhow::Result<BenchmarkResult> {
+    let workload = RUNTIME::benchmark_workload(config);
+    let client = reqwest::Client::new();
+    let api_url = config.api_url();
+
+    let elapsed = with_runtime::<RUNTIME, _>(config, model, async {
+        wait_for_api(&client, &api_url).await?;
+        log::info!("Benchmark warm up");
+        send_inference(&client, &api_url, &workload.request).await?;
+
+        log::info!("Benchmark of {requests} requests");
+        let started = Instant::now();
+        for _ in 0..requests {
+            send_inference(&client, &api_url, &workload.request).await?;
+        }
+        Ok(started.elapsed())
+    })
+    .await?;
+
+    let results = (requests * workload.results_per_request) as f64;
+    let score = results / elapsed.as_secs_f64();
+    log::info!("Benchmark result: {score:.3} {}", workload.metric);
+
+    Ok(BenchmarkResult {
+        runtime: runtime.to_string(),
+        gpu: BenchmarkGpu::detect(config),
+        metric: workload.metric,
+        score,
+        workload: workload.request.body,
+        timestamp: Utc::now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gpu(uuid: &str) -> BenchmarkGpu {
+        BenchmarkGpu {
+            uuid: Some(uuid.into()),
+            model: Some("NVIDIA GeForce RTX 3090".into()),
+        }
+    }
+
+    #[test]
+    fn offer_property_test() {
+        let path = std::env::temp_dir().join("ya-runtime-ai-benchmark-test.json");
+        let result = BenchmarkResult {
+            runtime: "automatic".into(),
+            gpu: gpu("GPU-1"),
+            metric: "images-per-sec".into(),
+            score: 2.5,
+            workload: json!({ "steps": 20 }),
+            timestamp: Utc::now(),
+        };
+        result.save(&path).unwrap();
+
+        let property = offer_property(&path, "automatic", &gpu("GPU-1")).unwrap();
+        assert_eq!(property["images-per-sec"], json!(2.5));
+        assert!(offer_property(&path, "dummy", &gpu("GPU-1")).is_none());
+        assert!(offer_property(&path, "automatic", &gpu("GPU-2")).is_none());
+
+        fs::write(&path, "{ corrupted").unwrap();
+        assert!(offer_property(&path, "automatic", &gpu("GPU-1")).is_none());
+
+        fs::remove_file(&path).unwrap();
+        assert!(offer_property(&path, "automatic", &gpu("GPU-1")).is_none());
+    }
+}
@@ -9,6 +9,7 @@ use std::{
 };
 
 use crate::process::find_file;
+use crate::self_test::ReportFormat;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about)]
@@ -22,6 +23,12 @@ pub struct Cli {
     /// Runtime config. A text in json format or a path to a json file.
     #[arg(long,value_parser = parse_runtime_config)]
     pub runtime_config: Option<serde_json::Value>,
+    /// Offer template overrides file path.
+    #[arg(long, env = "OFFER_OVERRIDE_FILE_PATH")]
+    pub offer_override_file: Option<PathBuf>,
+    /// Benchmark results file path. Defaults to `<runtime>-benchmark.json` in user data dir.
+    #[arg(long, env = "BENCHMARK_FILE_PATH")]
+    pub benchmark_file: Option<PathBuf>,
     #[command(subcommand)]
     pub command: Command,
 }
@@ -57,7 +64,29 @@ pub enum Command {
     /// Print an offer template in JSON format
     OfferTemplate,
     /// Run runtime's tests command
-    Test,
+    Test {
+        /// Common cache directory to check available disk space
+        #[arg(long, short)]
+        cache_dir: Option<PathBuf>,
+        /// Test report format
+        #[arg(long, value_enum, default_value_t)]
+        format: ReportFormat,
+        /// Start the runtime and send an inference request to it
+        #[arg(long)]
+        deep: bool,
+        /// Model used by deep test
+        #[arg(long, requires = "deep")]
+        model: Option<PathBuf>,
+    },
+    /// Run runtime's benchmark and store results published in the offer
+    Benchmark {
+        /// Model used by benchmark
+        #[arg(long)]
+        model: Option<PathBuf>,
+        /// Number of measured requests
+        #[arg(long, default_value_t = 5)]
+        requests: u32,
+    },
 }
 
 #[derive(Parser, Debug)]
@@ -0,0 +1,328 @@
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use serde::Deserialize;
+
+use ya_counters::error::CounterError;
+use ya_counters::{Counter, TimeCounter};
+
+use crate::proxy::{request_path, Endpoint, EndpointMatcher};
+
+pub(crate) const REQUESTS_COUNTER: &str = "ai-runtime.requests";
+pub(crate) const GPU_SEC_COUNTER: &str = "golem.usage.gpu-sec";
+/// Total time requests waited in proxy queue. Not published in the usage vector.
+pub(crate) const QUEUE_WAIT_SEC_COUNTER: &str = "ai-runtime.queue-wait-sec";
+
+/// Counters registered by exe-unit, published as offer usage vector.
+pub(crate) fn usage_vector(endpoints: &[EndpointClass]) -> Vec<String> {
+    let mut usage_vector = vec![
+        TimeCounter::ID.to_string(),
+        REQUESTS_COUNTER.to_string(),
+        GPU_SEC_COUNTER.to_string(),
+    ];
+    for endpoint in endpoints {
+        if !usage_vector.contains(&endpoint.counter) {
+            usage_vector.push(endpoint.counter.clone());
+        }
+    }
+    usage_vector
+}
+
+/// Class of proxied requests counted by `counter`.
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct EndpointClass {
+    #[serde(flatten)]
+    pub endpoint: Endpoint,
+    /// Counter increased by requests of the class.
+    #[serde(default = "default_counter")]
+    pub counter: String,
+    /// Counter increase per request.
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+
+fn default_counter() -> String {
+    REQUESTS_COUNTER.to_string()
+}
+
+fn default_weight() -> f64 {
+    1.0
+}
+
+struct Classifier {
+    endpoint: EndpointMatcher,
+    counter: String,
+    weight: f64,
+    value: Arc<Mutex<f64>>,
+}
+
+/// Counts proxied requests by endpoint class.
+/// Requests not matching any class are not counted.
+/// Without configured classes every request increases `ai-runtime.requests` counter.
+#[derive(Clone)]
+pub(crate) struct RequestCounters {
+    classes: Arc<Vec<Classifier>>,
+    counters: Vec<(String, Arc<Mutex<f64>>)>,
+    /// Processing time of counted requests, billed as `golem.usage.gpu-sec`.
+    busy: BusyTime,
+}
+
+impl RequestCounters {
+    pub fn new(endpoints: &[EndpointClass]) -> anyhow::Result<Self> {
+        let default_endpoints = [EndpointClass {
+            endpoint: Endpoint {
+                method: None,
+                path: ".*".into(),
+            },
+            counter: default_counter(),
+            weight: default_weight(),
+        }];
+        let endpoints = match endpoints {
+            [] => &default_endpoints,
+            endpoints => endpoints,
+        };
+
+        let mut counters: Vec<(String, Arc<Mutex<f64>>)> = Vec::new();
+        let mut classes = Vec::new();
+        for endpoint in endpoints {
+            let value = match counters.iter().find(|(id, _)| id == &endpoint.counter) {
+                Some((_, value)) => value.clone(),
+                None => {
+                    let value = Arc::new(Mutex::new(0.0));
+                    counters.push((endpoint.counter.clone(), value.clone()));
+                    value
+                }
+            };
+            classes.push(Classifier {
+                endpoint: endpoint.endpoint.matcher()?,
+                counter: endpoint.counter.clone(),
+                weight: endpoint.weight,
+                value,
+            });
+        }
+
+        Ok(Self {
+            classes: Arc::new(classes),
+            counters,
+            busy: Default::default(),
+        })
+    }
+
+    /// Counter of given id. Counter not used by any endpoint class stays at 0.
+    pub fn counter(&self, id: &str) -> RequestCounter {
+        let value = self
+            .counters
+            .iter()
+            .find(|(counter_id, _)| counter_id == id)
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default();
+        RequestCounter { value }
+    }
+
+    /// Counter of time (in seconds) the runtime processed counted requests.
+    pub fn gpu_sec_counter(&self) -> BusyTimeCounter {
+        BusyTimeCounter {
+            busy: self.busy.clone(),
+        }
+    }
+
+    /// Marks counted request as processed by the runtime until returned guard is dropped.
+    pub fn processing(&self) -> BusyGuard {
+        self.busy.start()
+    }
+
+    /// Ids of counters used by endpoint classes.
+    pub fn ids(&self) -> impl Iterator<Item = &str> {
+        self.counters.iter().map(|(id, _)| id.as_str())
+    }
+
+    /// Increases counter of the first class matching the request.
+    /// `path` has to be canonical (see [`crate::proxy::canonical_path`]) like paths passed by
+    /// the proxy, otherwise encoded or redirected forms of counted endpoints would not be billed.
+    /// Returns increased counter id and increase.
+    pub fn count(&self, method: &str, path: &str) -> Option<(String, f64)> {
+        let class = self
+            .classes
+            .iter()
+            .find(|class| class.endpoint.matches(method, path));
+        match class {
+            Some(class) => {
+                let mut value = class.value.lock().unwrap();
+                *value += class.weight;
+                Some((class.counter.clone(), class.weight))
+            }
+            None => {
+                log::debug!("Request {method} {} not counted", request_path(path));
+                None
+            }
+        }
+    }
+}
+
+pub(crate) struct RequestCounter {
+    value: Arc<Mutex<f64>>,
+}
+
+impl RequestCounter {
+    pub fn new(value: Arc<Mutex<f64>>) -> Self {
+        Self { value }
+    }
+}
+
+impl Counter for RequestCounter {
+    fn peak(&mut self) -> Result<f64, CounterError> {
+        Ok(*self.value.lock().unwrap())
+    }
+
+    fn sample(&mut self) -> Result<f64, CounterError> {
+        self.peak()
+    }
+}
+
+/// Time when at least one request was processed. Concurrent requests are not billed twice.
+#[derive(Clone, Default)]
+struct BusyTime {
+    state: Arc<Mutex<BusyState>>,
+}
+
+#[derive(Default)]
+struct BusyState {
+    in_flight: usize,
+    since: Option<Instant>,
+    total_sec: f64,
+}
+
+impl BusyTime {
+    fn start(&self) -> BusyGuard {
+        let mut state = self.state.lock().unwrap();
+        state.in_flight += 1;
+        state.since.get_or_insert_with(Instant::now);
+        BusyGuard { busy: self.clone() }
+    }
+
+    fn total_sec(&self) -> f64 {
+        let state = self.state.lock().unwrap();
+        let current = state
+            .since
+            .map_or(0.0, |since| since.elapsed().as_secs_f64());
+        state.total_sec + current
+    }
+}
+
+pub(crate) struct BusyGuard {
+    busy: BusyTime,
+}
+
+impl Drop for BusyGuard {
+    fn drop(&mut self) {
+        let mut state = self.busy.state.lock().unwrap();
+        state.in_flight -= 1;
+        if state.in_flight == 0 {
+            if let Some(since) = state.since.take() {
+                state.total_sec += since.elapsed().as_secs_f64();
+            }
+        }
+    }
+}
+
+pub(crate) struct BusyTimeCounter {
+    busy: BusyTime,
+}
+
+impl Counter for BusyTimeCounter {
+    fn peak(&mut self) -> Result<f64, CounterError> {
+        Ok(self.busy.total_sec())
+    }
+
+    fn sample(&mut self) -> Result<f64, CounterError> {
+        self.peak()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::process::automatic;
+    use crate::proxy::canonical_path;
+
+    fn sample(counters: &RequestCounters, id: &str) -> f64 {
+        counters.counter(id).sample().unwrap()
+    }
+
+    #[test]
+    fn default_endpoints_test() {
+        let counters = RequestCounters::new(&[]).unwrap();
+        counters.count("GET", "/sdapi/v1/options");
+        counters.count("POST", "/sdapi/v1/txt2img");
+
+        assert_eq!(sample(&counters, REQUESTS_COUNTER), 2.0);
+    }
+
+    #[test]
+    fn endpoint_classes_test() {
+        let endpoints: Vec<EndpointClass> = serde_json::from_value(json!([
+            { "method": "POST", "path": "sdapi/v1/txt2img" },
+            { "method": "POST", "path": "sdapi/v1/img2img", "weight": 2.0 },
+            { "method": "POST", "path": "sdapi/v1/extra-.*", "counter": "ai-runtime.extras" }
+        ]))
+        .unwrap();
+        let counters = RequestCounters::new(&endpoints).unwrap();
+
+        counters.count("post", "/sdapi/v1/txt2img");
+        counters.count("POST", "/sdapi/v1/img2img");
+        counters.count("GET", "/sdapi/v1/txt2img");
+        counters.count("GET", "/sdapi/v1/options");
+        counters.count("POST", "/sdapi/v1/txt2img/other");
+        counters.count("POST", "/sdapi/v1/extra-single-image");
+
+        assert_eq!(sample(&counters, REQUESTS_COUNTER), 3.0);
+        assert_eq!(sample(&counters, "ai-runtime.extras"), 1.0);
+        assert_eq!(
+            usage_vector(&endpoints),
+            vec![
+                TimeCounter::ID,
+                REQUESTS_COUNTER,
+                GPU_SEC_COUNTER,
+                "ai-runtime.extras"
+            ]
+        );
+    }
+
+    #[test]
+    fn gpu_sec_test() {
+        let counters = RequestCounters::new(&[]).unwrap();
+        let mut gpu_sec = counters.gpu_sec_counter();
+        assert_eq!(gpu_sec.sample().unwrap(), 0.0);
+
+        let started = Instant::now();
+        let first = counters.processing();
+        let second = counters.processing();
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        drop(first);
+        drop(second);
+        let total = gpu_sec.sample().unwrap();
+        // Concurrent requests are billed once.
+        assert!((0.02..=started.elapsed().as_secs_f64()).contains(&total));
+
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        assert_eq!(gpu_sec.sample().unwrap(), total);
+    }
+
+    #[test_case("/sdapi/v1/txt2img"; "canonical")]
+    #[test_case("/sdapi/v1/txt2img/"; "trailing slash")]
+    #[test_case("//sdapi/v1//txt2img"; "repeated slashes")]
+    #[test_case("/sdapi/v1/txt2im%67"; "encoded")]
+    #[test_case("/sdapi/v1/img2img%2F?x=1"; "encoded trailing slash")]
+    fn automatic_endpoints_test(path: &str) {
+        let endpoints = automatic::config::Config::default().endpoints;
+        let counters = RequestCounters::new(&endpoints).unwrap();
+
+        let counted = counters.count("POST", &canonical_path(path).unwrap());
+
+        assert_eq!(counted, Some((REQUESTS_COUNTER.to_string(), 1.0)));
+        assert_eq!(sample(&counters, REQUESTS_COUNTER), 1.0);
+    }
+}
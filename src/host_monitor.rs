@@ -0,0 +1,295 @@
+//! Suspends metering and proxying while host user is using the GPU.
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::Deserialize;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+use gpu_detection::GpuDetection;
+use ya_client_model::activity::activity_state::{ActivityState, State, StatePair};
+use ya_core_model::activity;
+use ya_counters::error::CounterError;
+use ya_counters::Counter;
+use ya_service_bus::typed as gsb;
+
+use crate::activity_error::ActivityError;
+use crate::metrics::Metrics;
+use crate::process::{ProcessController, Runtime};
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub(crate) struct HostMonitorConfig {
+    #[serde(with = "humantime_serde")]
+    pub check_interval: Duration,
+
+    /// Number of graphics processes (e.g. games) on the GPU tolerated before host is considered busy.
+    /// Display server and desktop shell are graphics processes too, so `0` fits only headless hosts.
+    /// `None` disables the check.
+    pub max_graphics_processes: Option<usize>,
+
+    /// Command (with args) checking if host is busy. Exit code other than 0 means host is busy.
+    pub hook: Vec<String>,
+
+    /// Hook not finished in time is killed and host is not considered busy.
+    #[serde(with = "humantime_serde")]
+    pub hook_timeout: Duration,
+}
+
+impl Default for HostMonitorConfig {
+    fn default() -> Self {
+        Self {
+            check_interval: Duration::from_secs(5),
+            max_graphics_processes: None,
+            hook: Vec::new(),
+            hook_timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Shared flag set while host is busy.
+#[derive(Clone, Default)]
+pub(crate) struct Suspension {
+    suspended: Arc<AtomicBool>,
+}
+
+impl Suspension {
+    pub fn is_suspended(&self) -> bool {
+        self.suspended.load(Ordering::SeqCst)
+    }
+
+    /// Returns `true` if suspension state changed.
+    fn set(&self, suspended: bool) -> bool {
+        self.suspended.swap(suspended, Ordering::SeqCst) != suspended
+    }
+
+    /// Wraps `counter` so its value does not grow while suspended.
+    pub fn pausable<C: Counter>(&self, counter: C) -> PausableCounter<C> {
+        PausableCounter {
+            counter,
+            suspension: self.clone(),
+            paused: None,
+            offset: 0.0,
+        }
+    }
+}
+
+pub(crate) struct PausableCounter<C: Counter> {
+    counter: C,
+    suspension: Suspension,
+    /// Counter values when suspension was noticed and last seen while suspended.
+    paused: Option<(f64, f64)>,
+    /// Sum of counter increments while suspended.
+    offset: f64,
+}
+
+impl<C: Counter> PausableCounter<C> {
+    fn adjust(&mut self, value: f64) -> f64 {
+        if self.suspension.is_suspended() {
+            let (paused_at, last) = self.paused.get_or_insert((value, value));
+            *last = value;
+            return *paused_at - self.offset;
+        }
+        if let Some((paused_at, last)) = self.paused.take() {
+            self.offset += last - paused_at;
+        }
+        value - self.offset
+    }
+}
+
+impl<C: Counter> Counter for PausableCounter<C> {
+    fn peak(&mut self) -> Result<f64, CounterError> {
+        let value = self.counter.peak()?;
+        Ok(self.adjust(value))
+    }
+
+    fn sample(&mut self) -> Result<f64, CounterError> {
+        let value = self.counter.sample()?;
+        Ok(self.adjust(value))
+    }
+}
+
+pub(crate) struct HostMonitor {
+    config: HostMonitorConfig,
+    gpu_uuid: Option<String>,
+    suspension: Suspension,
+    error: ActivityError,
+    metrics: Metrics,
+}
+
+impl HostMonitor {
+    pub fn new(
+        config: HostMonitorConfig,
+        gpu_uuid: Option<String>,
+        suspension: Suspension,
+        error: ActivityError,
+        metrics: Metrics,
+    ) -> Self {
+        Self {
+            config,
+            gpu_uuid,
+            suspension,
+            error,
+            metrics,
+        }
+    }
+
+    /// Checks host activity until `process` stops.
+    /// Activity state reason is updated whenever metering gets paused or resumed.
+    pub async fn run<T: Runtime + Clone + 'static>(
+        self,
+        report_url: String,
+        activity_id: String,
+        process: ProcessController<T>,
+    ) {
+        let gpu_detection = match self.config.max_graphics_processes {
+            Some(_) => match GpuDetection::init() {
+                Ok(gpu_detection) => Some(gpu_detection),
+                Err(err) => {
+                    log::error!("Host monitor failed to access GPU. Err {err}");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        while let Some(()) = process.report() {
+            let busy = match self.is_host_busy(gpu_detection.as_ref()).await {
+                Ok(busy) => busy,
+                Err(err) => {
+                    log::warn!("Failed to check host activity. Err {err}");
+                    false
+                }
+            };
+
+            if self.suspension.set(busy) {
+                let reason = if busy {
+                    log::info!("Host is using the GPU. Pausing metering and requests.");
+                    "Host is using the GPU. Metering paused"
+                } else {
+                    log::info!("Host released the GPU. Resuming metering and requests.");
+                    "Host released the GPU. Metering resumed"
+                };
+                self.metrics
+                    .set_activity_state(format!("{:?}", State::Ready));
+                set_reason(&report_url, &activity_id, reason, self.error.get()).await;
+            }
+
+            tokio::time::sleep(self.config.check_interval).await;
+        }
+    }
+
+    async fn is_host_busy(&self, gpu_detection: Option<&GpuDetection>) -> anyhow::Result<bool> {
+        if let (Some(gpu_detection), Some(max_graphics_processes)) =
+            (gpu_detection, self.config.max_graphics_processes)
+        {
+            let usage = gpu_detection.usage(self.gpu_uuid.as_ref())?;
+            if usage.graphics_processes > max_graphics_processes {
+                log::debug!("GPU graphics processes: {}", usage.graphics_processes);
+                return Ok(true);
+            }
+        }
+
+        if let Some((program, args)) = self.config.hook.split_first() {
+            let status = Command::new(PathBuf::from(program))
+                .args(args)
+                .kill_on_drop(true)
+                .status();
+            let status = timeout(self.config.hook_timeout, status)
+                .await
+                .context("Host monitor hook timeout")??;
+            if !status.success() {
+                log::debug!("Host monitor hook status: {status}");
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+async fn set_reason(
+    report_url: &str,
+    activity_id: &str,
+    reason: &str,
+    error_message: Option<String>,
+) {
+    if let Err(err) = gsb::service(report_url)
+        .call(activity::local::SetState {
+            activity_id: activity_id.into(),
+            state: ActivityState {
+                state: StatePair::from(State::Ready),
+                reason: Some(reason.into()),
+                error_message,
+            },
+            timeout: None,
+            credentials: None,
+        })
+        .await
+    {
+        log::error!("Failed to send state. Err {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct StepCounter {
+        value: f64,
+    }
+
+    impl Counter for StepCounter {
+        fn peak(&mut self) -> Result<f64, CounterError> {
+            self.sample()
+        }
+
+        fn sample(&mut self) -> Result<f64, CounterError> {
+            self.value += 1.0;
+            Ok(self.value)
+        }
+    }
+
+    #[test]
+    fn pausable_counter_test() {
+        let suspension = Suspension::default();
+        let mut counter = suspension.pausable(StepCounter { value: 0.0 });
+
+        assert_eq!(counter.sample().unwrap(), 1.0);
+        assert_eq!(counter.sample().unwrap(), 2.0);
+
+        suspension.set(true);
+        assert_eq!(counter.sample().unwrap(), 3.0);
+        assert_eq!(counter.sample().unwrap(), 3.0);
+        assert_eq!(counter.sample().unwrap(), 3.0);
+
+        suspension.set(false);
+        assert_eq!(counter.sample().unwrap(), 4.0);
+        assert_eq!(counter.sample().unwrap(), 5.0);
+    }
+
+    #[cfg(target_family = "unix")]
+    #[tokio::test]
+    async fn hook_timeout_test() {
+        let config = HostMonitorConfig {
+            max_graphics_processes: None,
+            hook: vec!["sleep".into(), "10".into()],
+            hook_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let monitor = HostMonitor::new(
+            config,
+            None,
+            Suspension::default(),
+            ActivityError::default(),
+            Metrics::default(),
+        );
+        let started = std::time::Instant::now();
+        assert!(monitor.is_host_busy(None).await.is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
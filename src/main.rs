@@ -10,10 +10,11 @@ use actix::prelude::*;
 use anyhow::Context;
 use chrono::Utc;
 use clap::Parser;
+use futures::future::LocalBoxFuture;
 use futures::prelude::*;
 use process::Runtime;
 use tokio::select;
-use tokio::sync::{mpsc, mpsc::Receiver, mpsc::Sender};
+use tokio::sync::mpsc::{self, Receiver, Sender, UnboundedReceiver, UnboundedSender};
 
 use ya_client_model::activity::activity_state::*;
 use ya_client_model::activity::ExeScriptCommand;
@@ -28,17 +29,39 @@ use ya_gsb_http_proxy::gsb_to_http::GsbToHttpProxy;
 use ya_service_bus::typed::{self as gsb, Endpoint};
 use ya_transfer::transfer::{DeployImage, Shutdown, TransferService, TransferServiceContext};
 
+use crate::activity_error::ActivityError;
 use crate::agreement::AgreementDesc;
+use crate::benchmark::{BenchmarkGpu, BENCHMARK_PROPERTY};
 use crate::cli::*;
+use crate::counters::{RequestCounters, GPU_SEC_COUNTER, QUEUE_WAIT_SEC_COUNTER, REQUESTS_COUNTER};
+use crate::host_monitor::{HostMonitor, Suspension};
 use crate::logger::*;
-use crate::process::ProcessController;
+use crate::metrics::Metrics;
+use crate::offer_template::OfferOverrides;
+use crate::process::output_log::{
+    OutputLog, OutputStream, DEFAULT_OUTPUT_LINES, OUTPUT_ENTRY_POINT,
+};
+use crate::process::{
+    FailureReaction, ProcessContext, ProcessController, RestartPolicy, RuntimeConfig,
+    RuntimeFailure,
+};
+use crate::proxy::{Proxy, RequestQueue};
+use crate::self_test::TestReport;
 use crate::signal::SignalMonitor;
 
+mod activity_error;
 mod agreement;
+mod benchmark;
 mod cli;
+mod counters;
+mod host_monitor;
 mod logger;
+mod metrics;
 mod offer_template;
 mod process;
+mod proxy;
+mod rotating_file;
+mod self_test;
 mod signal;
 
 pub type Signal = &'static str;
@@ -47,6 +70,8 @@ async fn send_state<T: process::Runtime>(
     ctx: &ExeUnitContext<T>,
     new_state: ActivityState,
 ) -> anyhow::Result<()> {
+    ctx.metrics
+        .set_activity_state(format!("{:?}", new_state.state.0));
     Ok(gsb::service(ctx.report_url.clone())
         .call(activity::local::SetState::new(
             ctx.activity_id.clone(),
@@ -57,17 +82,33 @@ async fn send_state<T: process::Runtime>(
 }
 
 async fn activity_loop<T: process::Runtime + Clone + Unpin + 'static>(
-    report_url: &str,
-    activity_id: &str,
-    process: ProcessController<T>,
+    ctx: ExeUnitContext<T>,
     counters: Addr<CountersService>,
+    mut failures: UnboundedReceiver<RuntimeFailure>,
+    restart_policy: RestartPolicy,
 ) -> anyhow::Result<()> {
+    let ExeUnitContext {
+        activity_id,
+        report_url,
+        agreement,
+        process_controller: process,
+        metrics,
+        error,
+        ..
+    } = ctx;
+    let (activity_id, counter_ids) = (activity_id.as_str(), agreement.counters);
     let report_service = gsb::service(report_url);
+    let mut restarts = Restarts {
+        policy: restart_policy,
+        count: 0,
+        pending: None,
+    };
 
     while let Some(()) = process.report() {
         match counters.send(GetCounters).await {
             Ok(resp) => match resp {
                 Ok(current_usage) => {
+                    metrics.set_usage(&counter_ids, &current_usage);
                     set_usage_msg(&report_service, activity_id, current_usage).await
                 }
                 Err(err) => match err {
@@ -75,6 +116,7 @@ async fn activity_loop<T: process::Runtime + Clone + Unpin + 'static>(
                         set_terminate_state_msg(
                             &report_service,
                             activity_id,
+                            &metrics,
                             Some(format!("Usage limit exceeded: {}", info)),
                             None,
                         )
@@ -92,13 +134,152 @@ async fn activity_loop<T: process::Runtime + Clone + Unpin + 'static>(
         select! {
             _ = tokio::time::sleep(Duration::from_secs(1)) => {},
             status = process.clone() => {
-                set_terminate_state_msg(&report_service, activity_id, Some("process exit".to_string()), Some(format!("status: {:?}", status))).await;
+                let mut error_message = format!("status: {:?}", status);
+                let recent_output = process.recent_output();
+                if !recent_output.is_empty() {
+                    error_message.push_str(&format!("\nLast output lines:\n{}", recent_output.join("\n")));
+                }
+                set_terminate_state_msg(&report_service, activity_id, &metrics, Some("process exit".to_string()), Some(error_message)).await;
                 log::error!("process exit: {:?}", status);
                 anyhow::bail!("Runtime exited");
             }
+            Some(failure) = failures.recv() => {
+                handle_runtime_failure(&report_service, activity_id, &process, &metrics, &error, &mut restarts, failure).await?;
+            }
+            result = async { restarts.pending.as_mut().unwrap().await }, if restarts.pending.is_some() => {
+                restarts.pending = None;
+                handle_restart(&report_service, activity_id, &metrics, &error, result).await?;
+            }
+        }
+    }
+    Ok(())
+}
 
+/// Runtime restarts done during the activity.
+struct Restarts {
+    policy: RestartPolicy,
+    count: u32,
+    /// Restart in progress. Polled by `activity_loop` next to usage reporting and exit detection.
+    pending: Option<LocalBoxFuture<'static, anyhow::Result<()>>>,
+}
+
+async fn handle_runtime_failure<T: process::Runtime + Clone + Unpin + 'static>(
+    report_service: &Endpoint,
+    activity_id: &str,
+    process: &ProcessController<T>,
+    metrics: &Metrics,
+    error: &ActivityError,
+    restarts: &mut Restarts,
+    failure: RuntimeFailure,
+) -> anyhow::Result<()> {
+    log::warn!(
+        "Runtime failure: {}. Reaction: {:?}",
+        failure.message,
+        failure.reaction
+    );
+    let reason = Some("Runtime failure".to_string());
+    match failure.reaction {
+        FailureReaction::Report => {
+            error.set(Some(failure.message.clone()));
+            set_ready_state_msg(
+                report_service,
+                activity_id,
+                metrics,
+                reason,
+                Some(failure.message),
+            )
+            .await;
         }
+        FailureReaction::Restart if restarts.pending.is_some() => {
+            log::info!("Runtime restart in progress. Failure ignored");
+        }
+        FailureReaction::Restart => {
+            let Some(backoff) = restarts.policy.backoff(restarts.count) else {
+                log::warn!("Runtime restart limit reached");
+                let message = format!(
+                    "{}\nRuntime restart limit of {} reached",
+                    failure.message, restarts.policy.max_restarts
+                );
+                return terminate_on_failure(
+                    report_service,
+                    activity_id,
+                    process,
+                    metrics,
+                    message,
+                )
+                .await;
+            };
+            error.set(Some(failure.message.clone()));
+            set_ready_state_msg(
+                report_service,
+                activity_id,
+                metrics,
+                reason,
+                Some(failure.message),
+            )
+            .await;
+            restarts.count += 1;
+            log::info!(
+                "Restarting runtime ({}/{}) after {} backoff",
+                restarts.count,
+                restarts.policy.max_restarts,
+                humantime::format_duration(backoff)
+            );
+            let process = process.clone();
+            restarts.pending = Some(async move { process.restart(backoff).await }.boxed_local());
+        }
+        FailureReaction::Terminate => {
+            terminate_on_failure(
+                report_service,
+                activity_id,
+                process,
+                metrics,
+                failure.message,
+            )
+            .await?;
+        }
+    }
+    Ok(())
+}
+
+async fn terminate_on_failure<T: process::Runtime + Clone + Unpin + 'static>(
+    report_service: &Endpoint,
+    activity_id: &str,
+    process: &ProcessController<T>,
+    metrics: &Metrics,
+    message: String,
+) -> anyhow::Result<()> {
+    let reason = Some("Runtime failure".to_string());
+    set_terminate_state_msg(report_service, activity_id, metrics, reason, Some(message)).await;
+    if let Err(err) = process.stop().await {
+        log::error!("Failed to stop runtime. Err {err}");
+    }
+    anyhow::bail!("Runtime failed");
+}
+
+async fn handle_restart(
+    report_service: &Endpoint,
+    activity_id: &str,
+    metrics: &Metrics,
+    error: &ActivityError,
+    result: anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    if let Err(err) = result {
+        set_terminate_state_msg(
+            report_service,
+            activity_id,
+            metrics,
+            Some("Runtime restart failure".to_string()),
+            Some(err.to_string()),
+        )
+        .await;
+        anyhow::bail!("Runtime restart failed. Err {err}");
     }
+    log::info!("Runtime restarted");
+    metrics.backend_started();
+    error.set(None);
+    let reason = Some("Runtime restarted".to_string());
+    set_ready_state_msg(report_service, activity_id, metrics, reason, None).await;
     Ok(())
 }
 
@@ -126,9 +307,11 @@ async fn set_usage_msg(report_service: &Endpoint, activity_id: &str, current_usa
 async fn set_terminate_state_msg(
     report_service: &Endpoint,
     activity_id: &str,
+    metrics: &Metrics,
     reason: Option<String>,
     error_message: Option<String>,
 ) {
+    metrics.set_activity_state(format!("{:?}", State::Terminated));
     if let Err(err) = report_service
         .call(activity::local::SetState {
             activity_id: activity_id.into(),
@@ -146,6 +329,32 @@ async fn set_terminate_state_msg(
     }
 }
 
+/// Keeps activity `Ready`, replacing its error message (cleared when `None`).
+async fn set_ready_state_msg(
+    report_service: &Endpoint,
+    activity_id: &str,
+    metrics: &Metrics,
+    reason: Option<String>,
+    error_message: Option<String>,
+) {
+    metrics.set_activity_state(format!("{:?}", State::Ready));
+    if let Err(err) = report_service
+        .call(activity::local::SetState {
+            activity_id: activity_id.into(),
+            state: ActivityState {
+                state: StatePair::from(State::Ready),
+                reason,
+                error_message,
+            },
+            timeout: None,
+            credentials: None,
+        })
+        .await
+    {
+        log::error!("Failed to send state. Err {err}");
+    }
+}
+
 #[actix_rt::main]
 async fn main() {
     let panic_hook = std::panic::take_hook();
@@ -214,6 +423,19 @@ struct ExeUnitContext<T: Runtime + 'static> {
     pub batches: Rc<RefCell<HashMap<String, Vec<ExeScriptCommandResult>>>>,
 
     pub model_path: Option<PathBuf>,
+
+    pub suspension: Suspension,
+
+    pub metrics: Metrics,
+
+    pub output_log: OutputLog,
+
+    pub runtime_failures: UnboundedSender<RuntimeFailure>,
+
+    pub error: ActivityError,
+
+    pub work_dir: PathBuf,
+    pub cache_dir: PathBuf,
 }
 
 async fn run<RUNTIME: process::Runtime + Clone + Unpin + 'static>(
@@ -238,12 +460,67 @@ async fn run<RUNTIME: process::Runtime + Clone + Unpin + 'static>(
             args,
         ),
         Command::OfferTemplate => {
-            let offer_template = RUNTIME::offer_template(&runtime_config)?;
+            let mut offer_template = RUNTIME::offer_template(&runtime_config)?;
+            match benchmark::file_path(cli.benchmark_file.as_deref(), &cli.runtime) {
+                Ok(benchmark_file) => {
+                    let gpu = BenchmarkGpu::detect(&runtime_config);
+                    if let Some(benchmark) =
+                        benchmark::offer_property(&benchmark_file, &cli.runtime, &gpu)
+                    {
+                        offer_template.set_property(BENCHMARK_PROPERTY, benchmark);
+                    }
+                }
+                Err(err) => log::warn!("No benchmark results. Err {err:#}"),
+            }
+            if let Some(overrides) = OfferOverrides::load(cli.offer_override_file.as_deref())? {
+                offer_template = overrides.apply(offer_template)?;
+            }
             let offer_template = serde_json::to_string_pretty(&offer_template)?;
             io::stdout().write_all(offer_template.as_bytes())?;
             return Ok(());
         }
-        Command::Test => return RUNTIME::test(&runtime_config),
+        Command::Test {
+            cache_dir,
+            format,
+            deep,
+            model,
+        } => {
+            let mut report = TestReport::new(&cli.runtime);
+            report.check("offer-overrides", || {
+                match OfferOverrides::load(cli.offer_override_file.as_deref())? {
+                    Some(_) => Ok("Valid offer overrides file".into()),
+                    None => Ok("No offer overrides file".into()),
+                }
+            });
+            RUNTIME::test(&runtime_config, &mut report);
+            match cache_dir {
+                Some(cache_dir) => report.check("disk-space", || {
+                    self_test::disk_space(cache_dir, runtime_config.min_free_disk_space_gib())
+                }),
+                None => report.skip("disk-space", "No cache dir"),
+            }
+            if *deep {
+                let result = self_test::smoke_test::<RUNTIME>(&runtime_config, model.clone()).await;
+                report.record("smoke-test", result);
+            } else {
+                report.skip("smoke-test", "Deep test not requested");
+            }
+            report.print(*format)?;
+            if !report.passed {
+                anyhow::bail!("Testing runtime failed");
+            }
+            return Ok(());
+        }
+        Command::Benchmark { model, requests } => {
+            let benchmark_file = benchmark::file_path(cli.benchmark_file.as_deref(), &cli.runtime)?;
+            let result =
+                benchmark::run::<RUNTIME>(&cli.runtime, &runtime_config, model.clone(), *requests)
+                    .await?;
+            result.save(&benchmark_file)?;
+            log::info!("Benchmark results saved to {benchmark_file:?}");
+            serde_json::to_writer_pretty(io::stdout(), &result)?;
+            return Ok(());
+        }
     };
 
     let runtime_config = Box::pin(runtime_config);
@@ -252,21 +529,58 @@ async fn run<RUNTIME: process::Runtime + Clone + Unpin + 'static>(
 
     let agreement = AgreementDesc::load(agreement_path)?;
 
-    let mut gsb_proxy = GsbToHttpProxy::new("http://localhost:7861/".into());
+    let gsb_proxy = GsbToHttpProxy::new(runtime_config.api_url());
+    let suspension = Suspension::default();
+    let request_counters = RequestCounters::new(&runtime_config.endpoints())?;
+    let request_queue = Rc::new(RequestQueue::new(runtime_config.queue())?);
+    let metrics = Metrics::new(
+        &activity_id,
+        &cli.runtime,
+        runtime_config.gpu_uuid(),
+        request_queue.depth_gauge(),
+    );
+    if let Some(address) = runtime_config.metrics_address() {
+        // Activity works without metrics, e.g. when the port is still taken by previous exe-unit.
+        if let Err(err) = metrics::serve(metrics.clone(), address) {
+            log::warn!("Failed to serve metrics at {address}. Err {err}");
+        }
+    }
 
     let mut counters = CountersServiceBuilder::new(agreement.counters.clone(), Some(10000));
     counters
-        .with_counter(TimeCounter::ID, Box::<TimeCounter>::default())
         .with_counter(
-            "ai-runtime.requests",
-            Box::new(gsb_proxy.requests_counter()),
+            TimeCounter::ID,
+            Box::new(suspension.pausable(TimeCounter::default())),
         )
         .with_counter(
-            "golem.usage.gpu-sec",
-            Box::new(gsb_proxy.requests_duration_counter()),
+            REQUESTS_COUNTER,
+            Box::new(suspension.pausable(request_counters.counter(REQUESTS_COUNTER))),
+        )
+        .with_counter(
+            GPU_SEC_COUNTER,
+            Box::new(suspension.pausable(request_counters.gpu_sec_counter())),
+        )
+        .with_counter(
+            QUEUE_WAIT_SEC_COUNTER,
+            Box::new(suspension.pausable(request_queue.wait_counter())),
+        );
+    for counter_id in request_counters.ids().filter(|id| {
+        ![
+            TimeCounter::ID,
+            REQUESTS_COUNTER,
+            GPU_SEC_COUNTER,
+            QUEUE_WAIT_SEC_COUNTER,
+        ]
+        .contains(id)
+    }) {
+        counters.with_counter(
+            counter_id,
+            Box::new(suspension.pausable(request_counters.counter(counter_id))),
         );
+    }
     let counters = counters.build().start();
 
+    let (runtime_failures, runtime_failures_rx) = mpsc::unbounded_channel();
     let ctx = ExeUnitContext {
         activity_id: activity_id.clone(),
         report_url: report_url.clone(),
@@ -280,13 +594,20 @@ async fn run<RUNTIME: process::Runtime + Clone + Unpin + 'static>(
         process_controller: process::ProcessController::<RUNTIME>::new(),
         batches: Rc::new(RefCell::new(Default::default())),
         model_path: None,
+        suspension: suspension.clone(),
+        metrics: metrics.clone(),
+        output_log: OutputLog::new(runtime_config.output_log(), &args.work_dir),
+        runtime_failures,
+        error: ActivityError::default(),
+        work_dir: args.work_dir.clone(),
+        cache_dir: args.cache_dir.clone(),
     };
 
     let activity_pinger = activity_loop(
-        report_url,
-        activity_id,
-        ctx.process_controller.clone(),
+        ctx.clone(),
         counters.clone(),
+        runtime_failures_rx,
+        runtime_config.restart_policy(),
     );
 
     #[cfg(target_os = "windows")]
@@ -368,15 +689,41 @@ async fn run<RUNTIME: process::Runtime + Clone + Unpin + 'static>(
                             .map_err(|e| RpcMessageError::Service(e.to_string()))?;
 
                             ctx.process_controller
-                                .start(ctx.model_path.clone(), (*runtime_config).clone())
+                                .start(
+                                    ctx.model_path.clone(),
+                                    (*runtime_config).clone(),
+                                    ProcessContext {
+                                        output: ctx.output_log.clone(),
+                                        metrics: ctx.metrics.clone(),
+                                        failures: Some(ctx.runtime_failures.clone()),
+                                        work_dir: ctx.work_dir.clone(),
+                                        cache_dir: ctx.cache_dir.clone(),
+                                    },
+                                )
                                 .await
                                 .map_err(|e| RpcMessageError::Activity(e.to_string()))?;
+                            ctx.metrics.backend_started();
                             log::debug!("Started process");
 
                             send_state(&ctx, ActivityState::from(StatePair(State::Ready, None)))
                                 .await
                                 .map_err(|e| RpcMessageError::Service(e.to_string()))?;
 
+                            if let Some(config) = runtime_config.host_monitor() {
+                                let host_monitor = HostMonitor::new(
+                                    config,
+                                    runtime_config.gpu_uuid(),
+                                    ctx.suspension.clone(),
+                                    ctx.error.clone(),
+                                    ctx.metrics.clone(),
+                                );
+                                tokio::task::spawn_local(host_monitor.run(
+                                    ctx.report_url.clone(),
+                                    ctx.activity_id.clone(),
+                                    ctx.process_controller.clone(),
+                                ));
+                            }
+
                             log::info!("Got start command, changing state of exe unit to ready",);
                             result.push(ExeScriptCommandResult {
                                 index: result.len() as u32,
@@ -410,6 +757,32 @@ async fn run<RUNTIME: process::Runtime + Clone + Unpin + 'static>(
                                 event_date: Utc::now(),
                             });
                         }
+                        ExeScriptCommand::Run {
+                            entry_point, args, ..
+                        } if entry_point == OUTPUT_ENTRY_POINT => {
+                            let lines = match args.first() {
+                                Some(lines) => lines.parse().map_err(|e| {
+                                    RpcMessageError::Activity(format!(
+                                        "Invalid number of output lines {lines:?}: {e}"
+                                    ))
+                                })?,
+                                None => DEFAULT_OUTPUT_LINES,
+                            };
+                            let tail = |stream| {
+                                ctx.output_log
+                                    .tail(stream, lines)
+                                    .map_err(|e| RpcMessageError::Activity(e.to_string()))
+                            };
+                            result.push(ExeScriptCommandResult {
+                                index: result.len() as u32,
+                                result: CommandResult::Ok,
+                                stdout: Some(tail(OutputStream::Stdout)?),
+                                stderr: Some(tail(OutputStream::Stderr)?),
+                                message: None,
+                                is_batch_finished: false,
+                                event_date: Utc::now(),
+                            });
+                        }
                         cmd => {
                             return Err(RpcMessageError::Activity(format!(
                                 "invalid command for ai runtime: {:?}",
@@ -465,8 +838,17 @@ async fn run<RUNTIME: process::Runtime + Clone + Unpin + 'static>(
             }
         });
 
-        gsb_proxy.bind(&exe_unit_url);
-        gsb_proxy.bind_streaming(&exe_unit_url);
+        let proxy = Proxy::new::<RUNTIME>(
+            gsb_proxy,
+            suspension,
+            request_counters,
+            request_queue,
+            &runtime_config,
+            &args.work_dir,
+            metrics,
+        )?;
+        proxy.bind(&exe_unit_url);
+        proxy.bind_streaming(&exe_unit_url);
     };
     send_state(
         &ctx,
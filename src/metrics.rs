@@ -0,0 +1,328 @@
+//! Prometheus metrics of the exe-unit, served on optional local endpoint.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use actix_web::{web, App, HttpResponse, HttpServer};
+
+use gpu_detection::GpuDetection;
+
+use crate::proxy::request_path;
+
+/// Request duration histogram buckets in seconds.
+const LATENCY_BUCKETS: &[f64] = &[0.1, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0];
+
+/// Limit of distinct request paths. Requests to other paths are reported as `other`.
+const MAX_PATHS: usize = 100;
+
+#[derive(Default)]
+struct Histogram {
+    /// Number of observations per bucket (not cumulative).
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if self.buckets.is_empty() {
+            self.buckets = vec![0; LATENCY_BUCKETS.len()];
+        }
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| value <= *bound) {
+            self.buckets[bucket] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct MetricsState {
+    counters: Vec<(String, f64)>,
+    latency: BTreeMap<String, Histogram>,
+    backend_starts: u64,
+    activity_state: Option<String>,
+    /// Values extracted from runtime output.
+    runtime_values: BTreeMap<String, f64>,
+}
+
+/// Exe-unit metrics shared with metrics endpoint.
+#[derive(Clone, Default)]
+pub(crate) struct Metrics {
+    activity_id: String,
+    runtime: String,
+    gpu_uuid: Option<String>,
+    queue_depth: Arc<AtomicUsize>,
+    state: Arc<Mutex<MetricsState>>,
+}
+
+impl Metrics {
+    pub fn new(
+        activity_id: &str,
+        runtime: &str,
+        gpu_uuid: Option<String>,
+        queue_depth: Arc<AtomicUsize>,
+    ) -> Self {
+        Self {
+            activity_id: activity_id.to_string(),
+            runtime: runtime.to_string(),
+            gpu_uuid,
+            queue_depth,
+            state: Default::default(),
+        }
+    }
+
+    /// Updates current values of agreement counters.
+    pub fn set_usage(&self, counter_ids: &[String], usage: &[f64]) {
+        let mut state = self.state.lock().unwrap();
+        state.counters = counter_ids
+            .iter()
+            .cloned()
+            .zip(usage.iter().copied())
+            .collect();
+    }
+
+    pub fn observe_request(&self, path: &str, duration: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let path = request_path(path);
+        let path = if state.latency.contains_key(path) || state.latency.len() < MAX_PATHS {
+            path
+        } else {
+            "other"
+        };
+        state
+            .latency
+            .entry(path.to_string())
+            .or_default()
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn backend_started(&self) {
+        self.state.lock().unwrap().backend_starts += 1;
+    }
+
+    pub fn set_activity_state(&self, activity_state: impl ToString) {
+        self.state.lock().unwrap().activity_state = Some(activity_state.to_string());
+    }
+
+    pub fn set_runtime_value(&self, name: &str, value: f64) {
+        let mut state = self.state.lock().unwrap();
+        state.runtime_values.insert(name.to_string(), value);
+    }
+
+    /// Metrics in Prometheus text format. GPU usage is included when `gpu` is available.
+    pub fn render(&self, gpu: Option<&GpuDetection>) -> String {
+        let mut out = String::new();
+        self.render_into(&mut out, gpu)
+            .expect("Writing to String does not fail");
+        out
+    }
+
+    fn render_into(&self, out: &mut String, gpu: Option<&GpuDetection>) -> std::fmt::Result {
+        let state = self.state.lock().unwrap();
+
+        writeln!(out, "# TYPE ya_runtime_ai_info gauge")?;
+        writeln!(
+            out,
+            "ya_runtime_ai_info{{activity_id=\"{}\",runtime=\"{}\"}} 1",
+            escape(&self.activity_id),
+            escape(&self.runtime)
+        )?;
+
+        writeln!(out, "# TYPE ya_runtime_ai_usage gauge")?;
+        for (counter, value) in &state.counters {
+            writeln!(
+                out,
+                "ya_runtime_ai_usage{{counter=\"{}\"}} {value}",
+                escape(counter)
+            )?;
+        }
+
+        writeln!(
+            out,
+            "# TYPE ya_runtime_ai_request_duration_seconds histogram"
+        )?;
+        for (path, histogram) in &state.latency {
+            let path = escape(path);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                writeln!(
+                    out,
+                    "ya_runtime_ai_request_duration_seconds_bucket{{path=\"{path}\",le=\"{bound}\"}} {cumulative}"
+                )?;
+            }
+            writeln!(
+                out,
+                "ya_runtime_ai_request_duration_seconds_bucket{{path=\"{path}\",le=\"+Inf\"}} {}",
+                histogram.count
+            )?;
+            writeln!(
+                out,
+                "ya_runtime_ai_request_duration_seconds_sum{{path=\"{path}\"}} {}",
+                histogram.sum
+            )?;
+            writeln!(
+                out,
+                "ya_runtime_ai_request_duration_seconds_count{{path=\"{path}\"}} {}",
+                histogram.count
+            )?;
+        }
+
+        writeln!(out, "# TYPE ya_runtime_ai_queue_depth gauge")?;
+        writeln!(
+            out,
+            "ya_runtime_ai_queue_depth {}",
+            self.queue_depth.load(Ordering::SeqCst)
+        )?;
+
+        writeln!(out, "# TYPE ya_runtime_ai_backend_starts_total counter")?;
+        writeln!(
+            out,
+            "ya_runtime_ai_backend_starts_total {}",
+            state.backend_starts
+        )?;
+
+        if let Some(activity_state) = &state.activity_state {
+            writeln!(out, "# TYPE ya_runtime_ai_activity_state gauge")?;
+            writeln!(
+                out,
+                "ya_runtime_ai_activity_state{{state=\"{}\"}} 1",
+                escape(activity_state)
+            )?;
+        }
+
+        writeln!(out, "# TYPE ya_runtime_ai_runtime_value gauge")?;
+        for (name, value) in &state.runtime_values {
+            writeln!(
+                out,
+                "ya_runtime_ai_runtime_value{{name=\"{}\"}} {value}",
+                escape(name)
+            )?;
+        }
+        drop(state);
+
+        let Some(gpu) = gpu else {
+            return Ok(());
+        };
+        match gpu.usage(self.gpu_uuid.as_ref()) {
+            Ok(usage) => {
+                writeln!(out, "# TYPE ya_runtime_ai_gpu_utilization_percent gauge")?;
+                writeln!(
+                    out,
+                    "ya_runtime_ai_gpu_utilization_percent {}",
+                    usage.utilization_percent
+                )?;
+                writeln!(out, "# TYPE ya_runtime_ai_gpu_free_memory_gib gauge")?;
+                writeln!(
+                    out,
+                    "ya_runtime_ai_gpu_free_memory_gib {}",
+                    usage.free_memory_gib
+                )?;
+                writeln!(out, "# TYPE ya_runtime_ai_gpu_graphics_processes gauge")?;
+                writeln!(
+                    out,
+                    "ya_runtime_ai_gpu_graphics_processes {}",
+                    usage.graphics_processes
+                )?;
+            }
+            Err(err) => log::debug!("GPU metrics not available. Err {err}"),
+        }
+        Ok(())
+    }
+}
+
+/// Escapes Prometheus label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Starts metrics endpoint serving `/metrics` on `address`.
+pub(crate) fn serve(metrics: Metrics, address: SocketAddr) -> anyhow::Result<()> {
+    // NVML is initialized once, not on every scrape.
+    let gpu = match GpuDetection::init() {
+        Ok(gpu) => Some(gpu),
+        Err(err) => {
+            log::warn!("GPU metrics not available. Err {err}");
+            None
+        }
+    };
+    let gpu = web::Data::new(gpu);
+    let server = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(metrics.clone()))
+            .app_data(gpu.clone())
+            .route("/metrics", web::get().to(metrics_handler))
+    })
+    .workers(1)
+    .disable_signals()
+    .bind(address)?;
+    for address in server.addrs() {
+        log::info!("Metrics available at http://{address}/metrics");
+    }
+    tokio::task::spawn_local(server.run());
+    Ok(())
+}
+
+async fn metrics_handler(
+    metrics: web::Data<Metrics>,
+    gpu: web::Data<Option<GpuDetection>>,
+) -> HttpResponse {
+    let body = web::block(move || metrics.render(gpu.get_ref().as_ref()))
+        .await
+        .unwrap_or_default();
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_test() {
+        let metrics = Metrics::new("activity-1", "dummy", None, Default::default());
+        metrics.set_usage(
+            &[
+                "golem.usage.duration_sec".into(),
+                "ai-runtime.requests".into(),
+            ],
+            &[12.5, 3.0],
+        );
+        metrics.observe_request("/sdapi/v1/txt2img", Duration::from_millis(700));
+        metrics.observe_request("/sdapi/v1/txt2img?x=1", Duration::from_secs(400));
+        metrics.backend_started();
+        metrics.set_activity_state("Ready");
+        metrics.set_runtime_value("model_load_sec", 5.2);
+
+        let rendered = metrics.render(None);
+        let expected = [
+            "ya_runtime_ai_info{activity_id=\"activity-1\",runtime=\"dummy\"} 1",
+            "ya_runtime_ai_usage{counter=\"golem.usage.duration_sec\"} 12.5",
+            "ya_runtime_ai_usage{counter=\"ai-runtime.requests\"} 3",
+            "ya_runtime_ai_request_duration_seconds_bucket{path=\"sdapi/v1/txt2img\",le=\"0.5\"} 0",
+            "ya_runtime_ai_request_duration_seconds_bucket{path=\"sdapi/v1/txt2img\",le=\"1\"} 1",
+            "ya_runtime_ai_request_duration_seconds_bucket{path=\"sdapi/v1/txt2img\",le=\"300\"} 1",
+            "ya_runtime_ai_request_duration_seconds_bucket{path=\"sdapi/v1/txt2img\",le=\"+Inf\"} 2",
+            "ya_runtime_ai_request_duration_seconds_count{path=\"sdapi/v1/txt2img\"} 2",
+            "ya_runtime_ai_queue_depth 0",
+            "ya_runtime_ai_backend_starts_total 1",
+            "ya_runtime_ai_activity_state{state=\"Ready\"} 1",
+            "ya_runtime_ai_runtime_value{name=\"model_load_sec\"} 5.2",
+        ];
+        for line in expected {
+            assert!(
+                rendered.lines().any(|rendered| rendered == line),
+                "{line} not found in:\n{rendered}"
+            );
+        }
+    }
+}
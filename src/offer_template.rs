@@ -1,16 +1,308 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use crate::counters::usage_vector;
 use crate::process::RuntimeConfig;
 
 use gpu_detection::model::Gpu;
 use gpu_detection::GpuDetection;
 use ya_agreement_utils::OfferTemplate;
 
+pub(crate) const GPU_PROPERTY: &str = "golem.!exp.gap-35.v1.inf.gpu";
+
+/// Runtime capabilities published in the offer under `golem.srv.comp.ai` property.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(rename_all(serialize = "kebab-case"), default)]
+pub(crate) struct Capabilities {
+    /// Supported runtime APIs.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub api: Vec<String>,
+    /// Supported model file formats.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub model_formats: Vec<String>,
+    /// Maximum generated image width and height.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_resolution: Option<u32>,
+}
+
 pub(crate) fn gpu_detection<CONFIG: RuntimeConfig>(config: &CONFIG) -> anyhow::Result<Gpu> {
     let gpu_detection = GpuDetection::init()?;
     Ok(gpu_detection.detect(config.gpu_uuid())?)
 }
 
-pub(crate) fn template<CONFIG: RuntimeConfig>(_config: &CONFIG) -> anyhow::Result<OfferTemplate> {
-    let offer_template = include_bytes!("offer-template.json");
-    let template: OfferTemplate = serde_json::from_slice(offer_template.as_ref())?;
-    Ok(template)
+/// Offer template with runtime counters and capabilities.
+pub(crate) fn template<CONFIG: RuntimeConfig>(config: &CONFIG) -> anyhow::Result<OfferTemplate> {
+    let template = json!({
+        "properties": {
+            "golem.com.usage.vector": usage_vector(&config.endpoints()),
+            "golem.srv.comp.ai": config.capabilities(),
+        },
+        "constraints": ""
+    });
+    Ok(serde_json::from_value(template)?)
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum OverrideMode {
+    /// Properties are deep merged. Constraints are joined with template constraints.
+    Merge,
+    /// Template properties or constraints are replaced by the overrides.
+    Replace,
+}
+
+/// Provider overrides of the offer template.
+/// By default properties are merged and constraints are replaced (as `OfferTemplate::patch` did).
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub(crate) struct OfferOverrides {
+    pub properties: Value,
+    pub constraints: String,
+    pub properties_mode: OverrideMode,
+    pub constraints_mode: OverrideMode,
+    /// Allows to override detected GPU properties.
+    pub allow_gpu_override: bool,
+}
+
+impl Default for OfferOverrides {
+    fn default() -> Self {
+        Self {
+            properties: Value::Null,
+            constraints: String::new(),
+            properties_mode: OverrideMode::Merge,
+            constraints_mode: OverrideMode::Replace,
+            allow_gpu_override: false,
+        }
+    }
+}
+
+impl OfferOverrides {
+    /// Fails when overrides file is set but it cannot be read or parsed,
+    /// so provider does not publish an offer without the overrides.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Option<Self>> {
+        let Some(path) = path else {
+            return Ok(None);
+        };
+        let file = fs::File::open(path)
+            .with_context(|| format!("Failed to open offer overrides file {path:?}"))?;
+        let overrides: OfferOverrides = serde_json::from_reader(file)
+            .with_context(|| format!("Failed to parse offer overrides file {path:?}"))?;
+        Ok(Some(overrides))
+    }
+
+    pub fn apply(self, template: OfferTemplate) -> anyhow::Result<OfferTemplate> {
+        let mut template = serde_json::to_value(template)?;
+        let template_properties = flatten(&template["properties"]);
+        let override_properties = flatten(&self.properties);
+
+        if !self.allow_gpu_override {
+            let modified_properties = match self.properties_mode {
+                OverrideMode::Merge => &override_properties,
+                OverrideMode::Replace => &template_properties,
+            };
+            if modified_properties.keys().any(|key| is_gpu_property(key)) {
+                anyhow::bail!(
+                    "Offer overrides modify detected {GPU_PROPERTY} properties (`allow_gpu_override` not set)"
+                );
+            }
+        }
+
+        let properties = match self.properties_mode {
+            OverrideMode::Merge => template_properties
+                .into_iter()
+                .chain(override_properties)
+                .collect(),
+            OverrideMode::Replace => override_properties,
+        };
+        template["properties"] = Value::Object(properties);
+
+        let constraints = template["constraints"].as_str().unwrap_or_default();
+        let constraints = match self.constraints_mode {
+            OverrideMode::Merge if constraints.trim().is_empty() => self.constraints,
+            OverrideMode::Merge if self.constraints.trim().is_empty() => constraints.to_string(),
+            OverrideMode::Merge => format!("(&{constraints}\n{})", self.constraints),
+            OverrideMode::Replace => self.constraints,
+        };
+        template["constraints"] = Value::String(constraints);
+
+        Ok(serde_json::from_value(template)?)
+    }
+}
+
+fn is_gpu_property(key: &str) -> bool {
+    key == GPU_PROPERTY || key.starts_with(&format!("{GPU_PROPERTY}."))
+}
+
+/// Flattens nested properties into dot separated keys.
+fn flatten(properties: &Value) -> Map<String, Value> {
+    fn flatten_into(prefix: Option<&str>, value: &Value, flat: &mut Map<String, Value>) {
+        match (prefix, value) {
+            (prefix, Value::Object(object)) => {
+                for (key, value) in object {
+                    let key = match prefix {
+                        Some(prefix) => format!("{prefix}.{key}"),
+                        None => key.clone(),
+                    };
+                    flatten_into(Some(&key), value, flat);
+                }
+            }
+            (Some(prefix), value) => {
+                flat.insert(prefix.to_string(), value.clone());
+            }
+            (None, _) => {}
+        }
+    }
+
+    let mut flat = Map::new();
+    flatten_into(None, properties, &mut flat);
+    flat
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template() -> OfferTemplate {
+        serde_json::from_value(json!({
+            "properties": {
+                "golem.com.usage.vector": ["golem.usage.duration_sec"],
+                "golem.!exp.gap-35.v1.inf.gpu": {
+                    "model": "NVIDIA GeForce RTX 3090",
+                    "memory": { "total.gib": 24.0 }
+                }
+            },
+            "constraints": "(golem.srv.comp.expiration>0)"
+        }))
+        .unwrap()
+    }
+
+    fn overrides(overrides: Value) -> OfferOverrides {
+        serde_json::from_value(overrides).unwrap()
+    }
+
+    fn properties(template: OfferTemplate) -> Value {
+        serde_json::to_value(template).unwrap()["properties"].clone()
+    }
+
+    #[test]
+    fn default_overrides_test() {
+        let overrides = overrides(json!({
+            "properties": { "golem.node.id.name": "provider" },
+            "constraints": "(golem.node.debug.subnet=public)"
+        }));
+        let template = serde_json::to_value(overrides.apply(template()).unwrap()).unwrap();
+
+        assert_eq!(
+            template["properties"]["golem.node.id.name"],
+            json!("provider")
+        );
+        assert_eq!(
+            template["properties"]["golem.com.usage.vector"],
+            json!(["golem.usage.duration_sec"])
+        );
+        assert_eq!(
+            template["constraints"],
+            json!("(golem.node.debug.subnet=public)")
+        );
+    }
+
+    #[test]
+    fn merge_overrides_test() {
+        let overrides = overrides(json!({
+            "properties": {
+                "golem": { "node": { "id": { "name": "provider" } } }
+            },
+            "constraints": "(golem.node.debug.subnet=public)",
+            "constraints_mode": "merge"
+        }));
+        let template = serde_json::to_value(overrides.apply(template()).unwrap()).unwrap();
+
+        assert_eq!(
+            template["properties"]["golem.node.id.name"],
+            json!("provider")
+        );
+        assert_eq!(
+            template["properties"]["golem.!exp.gap-35.v1.inf.gpu.model"],
+            json!("NVIDIA GeForce RTX 3090")
+        );
+        assert_eq!(
+            template["constraints"],
+            json!("(&(golem.srv.comp.expiration>0)\n(golem.node.debug.subnet=public))")
+        );
+    }
+
+    #[test]
+    fn replace_overrides_test() {
+        let overrides = overrides(json!({
+            "properties": { "golem.node.id.name": "provider" },
+            "constraints": "(golem.node.debug.subnet=public)",
+            "properties_mode": "replace",
+            "constraints_mode": "replace",
+            "allow_gpu_override": true
+        }));
+        let template = serde_json::to_value(overrides.apply(template()).unwrap()).unwrap();
+
+        assert_eq!(
+            template["properties"],
+            json!({ "golem.node.id.name": "provider" })
+        );
+        assert_eq!(
+            template["constraints"],
+            json!("(golem.node.debug.subnet=public)")
+        );
+    }
+
+    #[test]
+    fn gpu_overrides_not_allowed_test() {
+        let merge = overrides(json!({
+            "properties": { "golem.!exp.gap-35.v1.inf.gpu.model": "NVIDIA H100" }
+        }));
+        assert!(merge.apply(template()).is_err());
+
+        let replace = overrides(json!({
+            "properties": { "golem.node.id.name": "provider" },
+            "properties_mode": "replace"
+        }));
+        assert!(replace.apply(template()).is_err());
+    }
+
+    #[test]
+    fn gpu_overrides_allowed_test() {
+        let overrides = overrides(json!({
+            "properties": { "golem.!exp.gap-35.v1.inf.gpu.model": "NVIDIA H100" },
+            "allow_gpu_override": true
+        }));
+        let properties = properties(overrides.apply(template()).unwrap());
+
+        assert_eq!(
+            properties["golem.!exp.gap-35.v1.inf.gpu.model"],
+            json!("NVIDIA H100")
+        );
+    }
+
+    #[test]
+    fn load_overrides_test() {
+        assert!(OfferOverrides::load(None).unwrap().is_none());
+
+        let path = std::env::temp_dir().join("ya-runtime-ai-offer-overrides-test.json");
+        assert!(OfferOverrides::load(Some(&path.with_extension("missing"))).is_err());
+
+        fs::write(&path, "{ not json").unwrap();
+        assert!(OfferOverrides::load(Some(&path)).is_err());
+
+        fs::write(
+            &path,
+            r#"{ "constraints": "(golem.node.debug.subnet=public)" }"#,
+        )
+        .unwrap();
+        let overrides = OfferOverrides::load(Some(&path)).unwrap().unwrap();
+        assert_eq!(overrides.constraints_mode, OverrideMode::Replace);
+        assert_eq!(overrides.properties_mode, OverrideMode::Merge);
+
+        fs::remove_file(&path).unwrap();
+    }
 }
@@ -1,9 +1,13 @@
 use anyhow::Context;
 use async_trait::async_trait;
 use bytes::{Buf, BytesMut};
+use chrono::{DateTime, Utc};
 use futures::TryFutureExt;
 use serde::de::DeserializeOwned;
+use serde::Deserialize;
 use serde_json::Value;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
 use tokio::{io::BufReader, process::Child};
 use tokio_stream::StreamExt;
 use tokio_util::codec::{Decoder, FramedRead};
@@ -12,19 +16,133 @@ use std::cell::RefCell;
 use std::env::current_exe;
 use std::fmt::Debug;
 use std::future::Future;
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
 use std::pin::pin;
 use std::pin::Pin;
 use std::process::ExitStatus;
 use std::rc::Rc;
 use std::task::Poll;
+use std::time::Duration;
 
+use gpu_detection::GpuDetection;
 use ya_agreement_utils::OfferTemplate;
 
-use crate::offer_template::{self, gpu_detection};
+use crate::benchmark::Workload;
+use crate::counters::EndpointClass;
+use crate::host_monitor::HostMonitorConfig;
+use crate::metrics::Metrics;
+use crate::offer_template::{self, gpu_detection, Capabilities, GPU_PROPERTY};
+use crate::proxy::{AuditConfig, EndpointAccess, EndpointTimeout, QueueConfig, RequestFilter};
+use crate::self_test::{self, TestReport};
 
 pub mod automatic;
 pub mod dummy;
+pub(crate) mod env;
+pub(crate) mod output_log;
+
+use output_log::{OutputLog, OutputLogConfig};
+
+pub use output_log::OutputStream;
+
+/// Activity resources shared with the runtime process.
+#[derive(Clone)]
+pub(crate) struct ProcessContext {
+    pub output: OutputLog,
+    pub metrics: Metrics,
+    /// Receives failures detected after the runtime has started.
+    pub failures: Option<mpsc::UnboundedSender<RuntimeFailure>>,
+    pub work_dir: PathBuf,
+    pub cache_dir: PathBuf,
+}
+
+/// Context of runtime started outside of an activity (e.g. by `test` command).
+impl Default for ProcessContext {
+    fn default() -> Self {
+        Self {
+            output: Default::default(),
+            metrics: Default::default(),
+            failures: None,
+            work_dir: ".".into(),
+            cache_dir: ".".into(),
+        }
+    }
+}
+
+impl ProcessContext {
+    pub fn report_failure(&self, failure: RuntimeFailure) {
+        let Some(failures) = &self.failures else {
+            log::error!("Runtime failure not handled: {}", failure.message);
+            return;
+        };
+        if let Err(err) = failures.send(failure) {
+            log::error!("Runtime failure not handled: {}", err.0.message);
+        }
+    }
+
+    /// Creates working directory of runtime process.
+    /// Relative `dir` is resolved against activity work dir, which is used when `dir` is not set.
+    pub fn runtime_dir(&self, dir: Option<&Path>) -> anyhow::Result<PathBuf> {
+        let dir = match dir {
+            Some(dir) => self.work_dir.join(dir),
+            None => self.work_dir.clone(),
+        };
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Failed to create runtime work dir {dir:?}"))?;
+        Ok(dir)
+    }
+}
+
+/// Failure of running runtime detected in its output.
+#[derive(Clone, Debug)]
+pub(crate) struct RuntimeFailure {
+    pub reaction: FailureReaction,
+    pub message: String,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum FailureReaction {
+    /// Sets failure message as activity error.
+    #[default]
+    Report,
+    /// Starts the runtime again.
+    Restart,
+    /// Terminates the activity.
+    Terminate,
+}
+
+/// Limits of runtime restarts on failures with `restart` reaction.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub(crate) struct RestartPolicy {
+    /// Restarts allowed during the activity. Failure over the limit terminates the activity.
+    pub max_restarts: u32,
+
+    /// Delay between runtime stop and start, doubled on every next restart.
+    #[serde(with = "humantime_serde")]
+    pub backoff: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            max_restarts: 3,
+            backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RestartPolicy {
+    /// Backoff of the next restart after `restarts` already done. `None` when limit is reached.
+    pub fn backoff(&self, restarts: u32) -> Option<Duration> {
+        if restarts >= self.max_restarts {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(restarts);
+        Some(self.backoff.saturating_mul(factor))
+    }
+}
 
 #[allow(unused)]
 #[derive(Default, Clone)]
@@ -43,17 +161,40 @@ pub(crate) trait Runtime: Sized {
         }
     }
 
-    async fn start(mode: Option<PathBuf>, config: Self::CONFIG) -> anyhow::Result<Self>;
+    async fn start(
+        mode: Option<PathBuf>,
+        config: Self::CONFIG,
+        ctx: ProcessContext,
+    ) -> anyhow::Result<Self>;
 
     async fn stop(&mut self) -> anyhow::Result<()>;
 
     async fn wait(&mut self) -> std::io::Result<ExitStatus>;
 
-    fn test(config: &Self::CONFIG) -> anyhow::Result<()> {
-        gpu_detection(config).map_err(|err| {
-            anyhow::anyhow!("Testing runtime failed. Unable to detect GPU. Error: {err}")
-        })?;
-        Ok(())
+    /// Last lines of runtime output attached to activity error messages.
+    fn recent_output(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn test(config: &Self::CONFIG, report: &mut TestReport) {
+        report.check("gpu", || self_test::gpu(config));
+    }
+
+    /// Minimal inference request sent to the runtime API by smoke tests.
+    fn inference_request(config: &Self::CONFIG) -> InferenceRequest;
+
+    /// Standard workload measured by `benchmark` command.
+    fn benchmark_workload(config: &Self::CONFIG) -> Workload;
+
+    /// Validation of requestor's requests passed to the runtime API.
+    fn request_filter(_config: &Self::CONFIG) -> Option<Rc<dyn RequestFilter>> {
+        None
+    }
+
+    /// Runtime API path (called with `POST`) interrupting currently processed request.
+    /// Called when request times out or requestor stops waiting for the response.
+    fn cancel_path(_config: &Self::CONFIG) -> Option<String> {
+        None
     }
 
     fn offer_template(config: &Self::CONFIG) -> anyhow::Result<OfferTemplate> {
@@ -62,20 +203,133 @@ pub(crate) trait Runtime: Sized {
             anyhow::anyhow!("Generating offer template failed. Unable to detect GPU. Error: {err}")
         })?;
         let gpu = serde_json::value::to_value(gpu)?;
-        template.set_property("golem.!exp.gap-35.v1.inf.gpu", gpu);
+        template.set_property(GPU_PROPERTY, gpu);
         Ok(template)
     }
+
+    /// Checks if GPU is not taken over by other applications before runtime start.
+    /// Check is skipped when runtime config has no GPU usage limits.
+    fn check_gpu_availability(config: &Self::CONFIG) -> anyhow::Result<()> {
+        let max_utilization = config.max_gpu_utilization();
+        let min_free_memory_gib = config.min_free_gpu_memory_gib();
+        if max_utilization.is_none() && min_free_memory_gib.is_none() {
+            return Ok(());
+        }
+
+        let usage = GpuDetection::init()?.usage(config.gpu_uuid())?;
+        log::debug!("GPU usage: {usage:?}");
+
+        if let Some(max_utilization) = max_utilization {
+            if usage.utilization_percent > max_utilization {
+                anyhow::bail!(
+                    "GPU is busy. Utilization {}% exceeds {}% limit",
+                    usage.utilization_percent,
+                    max_utilization
+                );
+            }
+        }
+        if let Some(min_free_memory_gib) = min_free_memory_gib {
+            if usage.free_memory_gib < min_free_memory_gib {
+                anyhow::bail!(
+                    "Not enough free GPU memory. Available {:.2} GiB, required {:.2} GiB",
+                    usage.free_memory_gib,
+                    min_free_memory_gib
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `POST` request with JSON body sent to runtime API.
+pub(crate) struct InferenceRequest {
+    pub path: String,
+    pub body: Value,
 }
 
 pub(crate) trait RuntimeConfig: DeserializeOwned + Default + Debug + Clone {
     fn gpu_uuid(&self) -> Option<String>;
+
+    /// Runtime API base URL used by GSB to HTTP proxy.
+    fn api_url(&self) -> String {
+        "http://localhost:7861/".into()
+    }
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// Maximum GPU utilization (in percent) allowing to start the runtime.
+    fn max_gpu_utilization(&self) -> Option<u32> {
+        None
+    }
+
+    /// Minimum free GPU memory (in GiB) allowing to start the runtime.
+    fn min_free_gpu_memory_gib(&self) -> Option<f32> {
+        None
+    }
+
+    /// Host activity monitoring pausing metering while host is using the GPU.
+    fn host_monitor(&self) -> Option<HostMonitorConfig> {
+        None
+    }
+
+    /// Minimum free disk space (in GiB) in cache dir required by the runtime.
+    fn min_free_disk_space_gib(&self) -> Option<f32> {
+        None
+    }
+
+    /// Classes of proxied requests counted by request counters.
+    fn endpoints(&self) -> Vec<EndpointClass> {
+        Vec::new()
+    }
+
+    /// Runtime API endpoints available to requestors.
+    fn access(&self) -> EndpointAccess {
+        EndpointAccess::default()
+    }
+
+    /// Limit of requests passed to the runtime at once.
+    fn queue(&self) -> QueueConfig {
+        QueueConfig::default()
+    }
+
+    /// Time limits of proxied requests.
+    fn timeouts(&self) -> Vec<EndpointTimeout> {
+        Vec::new()
+    }
+
+    /// Log of proxied requests written to activity work dir.
+    fn audit(&self) -> Option<AuditConfig> {
+        None
+    }
+
+    /// Address of Prometheus metrics endpoint.
+    fn metrics_address(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// Files with runtime process output written to activity work dir.
+    fn output_log(&self) -> OutputLogConfig {
+        OutputLogConfig::default()
+    }
+
+    /// Limits of runtime restarts on failures.
+    fn restart_policy(&self) -> RestartPolicy {
+        RestartPolicy::default()
+    }
 }
 
+/// Time given to the runtime to stop before it is killed on restart.
+const RESTART_STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
 #[derive(Clone)]
 pub(crate) struct ProcessController<T: Runtime + 'static> {
     inner: Rc<RefCell<ProcessControllerInner<T>>>,
+    /// Arguments of the last `start` used on restart.
+    started: Rc<RefCell<Option<StartArgs<T>>>>,
 }
 
+type StartArgs<T> = (Option<PathBuf>, <T as Runtime>::CONFIG, ProcessContext);
+
 #[allow(clippy::large_enum_variant)]
 enum ProcessControllerInner<T: Runtime + 'static> {
     Deployed,
@@ -99,6 +353,7 @@ impl<RUNTIME: Runtime + Clone + 'static> ProcessController<RUNTIME> {
     pub fn new() -> Self {
         ProcessController {
             inner: Rc::new(RefCell::new(ProcessControllerInner::Deployed {})),
+            started: Default::default(),
         }
     }
 
@@ -123,8 +378,14 @@ impl<RUNTIME: Runtime + Clone + 'static> ProcessController<RUNTIME> {
         &self,
         model: Option<PathBuf>,
         config: RUNTIME::CONFIG,
+        ctx: ProcessContext,
     ) -> anyhow::Result<()> {
-        let child = RUNTIME::start(model, config)
+        RUNTIME::check_gpu_availability(&config)
+            .inspect_err(|err| log::error!("GPU is not available. Err {err}"))?;
+
+        self.started
+            .replace(Some((model.clone(), config.clone(), ctx.clone())));
+        let child = RUNTIME::start(model, config, ctx)
             .inspect_err(|err| log::error!("Failed to start process. Err {err}"))
             .await?;
 
@@ -133,6 +394,37 @@ impl<RUNTIME: Runtime + Clone + 'static> ProcessController<RUNTIME> {
 
         Ok(())
     }
+
+    pub fn recent_output(&self) -> Vec<String> {
+        match *self.inner.borrow() {
+            ProcessControllerInner::Working { ref child } => child.recent_output(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Stops the runtime and starts it again after `backoff` with the last used model and config.
+    /// Activity stays deployed in the meantime.
+    pub async fn restart(&self, backoff: Duration) -> anyhow::Result<()> {
+        let (model, config, ctx) = self
+            .started
+            .borrow()
+            .clone()
+            .context("Runtime has not been started")?;
+        let old = self.inner.replace(ProcessControllerInner::Deployed {});
+        if let ProcessControllerInner::Working { mut child } = old {
+            log::info!("Restarting runtime");
+            if let Err(err) = child.stop().await {
+                log::warn!("Failed to stop runtime. Err {err}");
+            }
+            match timeout(RESTART_STOP_TIMEOUT, child.wait()).await {
+                Ok(status) => log::debug!("Runtime stopped with status {status:?}"),
+                // Process is killed on drop.
+                Err(_) => log::warn!("Runtime did not stop in {RESTART_STOP_TIMEOUT:?}"),
+            }
+        }
+        tokio::time::sleep(backoff).await;
+        self.start(model, config, ctx).await
+    }
 }
 
 impl<T: Runtime> Future for ProcessController<T> {
@@ -149,22 +441,56 @@ impl<T: Runtime> Future for ProcessController<T> {
     }
 }
 
+/// Default limit of process output line length (in bytes).
+pub const DEFAULT_MAX_LINE_LENGTH: usize = 4096;
+
 pub struct LossyLinesCodec {
     max_length: usize,
+    /// Skipping rest of the line exceeding `max_length`.
+    discarding: bool,
 }
 
-impl Default for LossyLinesCodec {
-    fn default() -> Self {
+impl LossyLinesCodec {
+    pub fn new(max_length: usize) -> Self {
         Self {
-            max_length: usize::MAX,
+            max_length: max_length.max(1),
+            discarding: false,
         }
     }
 }
 
-pub type OutputLines = Pin<Box<dyn futures::Stream<Item = anyhow::Result<String>> + Send>>;
+impl Default for LossyLinesCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_LINE_LENGTH)
+    }
+}
+
+/// Line decoded by `LossyLinesCodec`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedLine {
+    pub text: String,
+    /// Line ended with carriage return, so it gets overwritten by the next one (progress bar redraw).
+    pub redraw: bool,
+}
+
+/// Line of process output.
+#[derive(Clone, Debug)]
+pub struct OutputLine {
+    pub stream: OutputStream,
+    pub timestamp: DateTime<Utc>,
+    pub text: String,
+    /// See `DecodedLine::redraw`.
+    pub redraw: bool,
+}
+
+pub type OutputLines = Pin<Box<dyn futures::Stream<Item = anyhow::Result<OutputLine>> + Send>>;
 
-/// Reads process stdout and stderr using `LossyLinesCodec`
-pub fn process_output(child: &mut Child) -> anyhow::Result<OutputLines> {
+/// Reads process stdout and stderr using `LossyLinesCodec` and writes them to `output` log.
+pub(crate) fn process_output(
+    child: &mut Child,
+    output: &OutputLog,
+    max_line_length: usize,
+) -> anyhow::Result<OutputLines> {
     let stdout = child
         .stdout
         .take()
@@ -174,71 +500,238 @@ pub fn process_output(child: &mut Child) -> anyhow::Result<OutputLines> {
         .take()
         .context("Failed to access process stderr")?;
 
-    let stdout = FramedRead::new(BufReader::new(stdout), LossyLinesCodec::default());
-    let stderr = FramedRead::new(BufReader::new(stderr), LossyLinesCodec::default());
+    let stdout = FramedRead::new(
+        BufReader::new(stdout),
+        LossyLinesCodec::new(max_line_length),
+    )
+    .map(tag_output(output.clone(), OutputStream::Stdout));
+    let stderr = FramedRead::new(
+        BufReader::new(stderr),
+        LossyLinesCodec::new(max_line_length),
+    )
+    .map(tag_output(output.clone(), OutputStream::Stderr));
 
     Ok(futures::StreamExt::boxed(stdout.merge(stderr)))
 }
 
-/// Decodes lines as UTF-8 (lossly) up to `max_length` characters per line.
+/// Tags lines with their stream and read time, and writes them to `output` log.
+fn tag_output(
+    output: OutputLog,
+    stream: OutputStream,
+) -> impl FnMut(anyhow::Result<DecodedLine>) -> anyhow::Result<OutputLine> {
+    move |line| {
+        let DecodedLine { text, redraw } = line?;
+        let line = OutputLine {
+            stream,
+            timestamp: Utc::now(),
+            text,
+            redraw,
+        };
+        output.write(&line);
+        Ok(line)
+    }
+}
+
+fn is_line_boundary(byte: &u8) -> bool {
+    // Carriage return ends progress bar redraws.
+    *byte == b'\n' || *byte == b'\r'
+}
+
+// Process output on Windows is encoded in UTF-16. To avoid OS specific implementation of process output handling the output is lossy converted to UTF-8.
+// It allows to avoid errors when decoding some Windows error log messages.
+fn lossy_line(line: &[u8], redraw: bool) -> DecodedLine {
+    DecodedLine {
+        text: String::from_utf8_lossy(line).to_string(),
+        redraw,
+    }
+}
+
+/// Decodes non empty lines as UTF-8 (lossly) up to `max_length` bytes per line.
+/// Longer lines are truncated and their remaining bytes are discarded up to the next line boundary.
+/// Lines ended with a carriage return alone are marked as progress bar redraws.
 impl Decoder for LossyLinesCodec {
-    type Item = String;
+    type Item = DecodedLine;
 
     type Error = anyhow::Error;
 
     fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let read_to = std::cmp::min(self.max_length.saturating_add(1), buf.len());
-        let new_line_offset = buf[0..read_to].iter().position(|b| *b == b'\n');
-        let has_new_line = new_line_offset.is_some();
-        let offset = new_line_offset.unwrap_or(read_to);
-        let line = buf.split_to(offset);
-        if has_new_line {
-            // Move cursor pass new line character so next call of `decode` will not read it.
-            buf.advance(1);
-        }
-        let mut line: &[u8] = &line;
-        if let Some(&b'\r') = line.last() {
-            // Skip carriage return.
-            line = &line[..line.len() - 1];
+        loop {
+            if self.discarding {
+                match buf.iter().position(is_line_boundary) {
+                    Some(offset) => {
+                        buf.advance(offset + 1);
+                        self.discarding = false;
+                    }
+                    None => {
+                        buf.clear();
+                        return Ok(None);
+                    }
+                }
+            }
+
+            let read_to = std::cmp::min(self.max_length.saturating_add(1), buf.len());
+            match buf[0..read_to].iter().position(is_line_boundary) {
+                Some(offset) => {
+                    let redraw = match buf.get(offset..offset + 2) {
+                        Some(b"\r\n") => false,
+                        Some(boundary) => boundary[0] == b'\r',
+                        // Wait for the next byte to tell `\r\n` from a redraw.
+                        None if buf[offset] == b'\r' => return Ok(None),
+                        None => false,
+                    };
+                    let line = buf.split_to(offset);
+                    // Move cursor pass line boundary so next call of `decode` will not read it.
+                    buf.advance(1);
+                    if !line.is_empty() {
+                        return Ok(Some(lossy_line(&line, redraw)));
+                    }
+                }
+                None if buf.len() > self.max_length => {
+                    let line = buf.split_to(self.max_length);
+                    self.discarding = true;
+                    return Ok(Some(lossy_line(&line, false)));
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        if let Some(line) = self.decode(buf)? {
+            return Ok(Some(line));
         }
+        // Last line without line boundary.
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        let line = buf.split();
+        let line = line.strip_suffix(b"\r").unwrap_or(&line[..]);
         if line.is_empty() {
             return Ok(None);
         }
-        // Process output on Windows is encoded in UTF-16. To avoid OS specific implementation of process output handling the output is lossy converted to UTF-8.
-        // It allows to avoid errors when decoding some Windows error log messages.
-        let line = String::from_utf8_lossy(line).to_string();
-        Ok(Some(line))
+        Ok(Some(lossy_line(line, false)))
     }
 }
 
 #[cfg(test)]
 mod tests {
 
+    use bytes::BytesMut;
     use test_case::test_case;
 
     use tokio_stream::StreamExt;
-    use tokio_util::codec::FramedRead;
+    use tokio_util::codec::{Decoder, FramedRead};
+
+    use std::time::Duration;
 
-    use super::LossyLinesCodec;
+    use super::{DecodedLine, LossyLinesCodec, ProcessContext, RestartPolicy};
+
+    async fn decode(encoded: &[u8], codec: LossyLinesCodec) -> Vec<String> {
+        let mut reader = FramedRead::new(encoded, codec);
+        let mut decoded = Vec::new();
+        while let Some(line) = reader.next().await {
+            match line {
+                Ok(line) => decoded.push(line.text),
+                Err(e) => panic!("Error reading line: {}", e),
+            }
+        }
+        decoded
+    }
 
     #[test_case("foo\nbar\nbaz".as_bytes(), &["foo", "bar", "baz"]; "CL multi line")]
     #[test_case("foo\r\nbar\r\nbaz".as_bytes(), &["foo", "bar", "baz"]; "CRCL multi line")]
     #[test_case("foo".as_bytes(), &["foo"]; "one line")]
     #[test_case("fóó\r\nbąr\r\nbąż".as_bytes(), &["fóó", "bąr", "bąż"];  "diacritics in UTF-8")]
     #[test_case("".as_bytes(), &[]; "empty")]
+    #[test_case("foo\n\n\nbar\n".as_bytes(), &["foo", "bar"]; "empty lines")]
+    #[test_case("load\r10%\r20%\ndone".as_bytes(), &["load", "10%", "20%", "done"]; "progress bar")]
     #[test_case(&[0x66, 0x6F, 0x80], &["fo�"]; "invalid characters")]
     #[tokio::test]
     async fn lines_codec_test(encoded: &[u8], expected: &[&str]) {
-        let mut reader: FramedRead<&[u8], LossyLinesCodec> =
-            FramedRead::new(encoded, LossyLinesCodec::default());
-        let mut decoded = Vec::new();
-        while let Some(line) = reader.next().await {
-            match line {
-                Ok(line) => decoded.push(line),
-                Err(e) => panic!("Error reading line: {}", e),
-            }
-        }
+        let decoded = decode(encoded, LossyLinesCodec::default()).await;
+        let decoded = decoded.iter().map(String::as_str).collect::<Vec<&str>>();
+        assert_eq!(expected, decoded.as_slice());
+    }
+
+    #[test_case("abcd\nefgh".as_bytes(), &["abcd", "efgh"]; "max length lines")]
+    #[test_case("abcdefgh\nxy".as_bytes(), &["abcd", "xy"]; "truncated line")]
+    #[test_case("abcdefgh".as_bytes(), &["abcd"]; "truncated last line")]
+    #[test_case("abcdef\rgh\r\n".as_bytes(), &["abcd", "gh"]; "truncated progress bar")]
+    #[tokio::test]
+    async fn max_length_test(encoded: &[u8], expected: &[&str]) {
+        let decoded = decode(encoded, LossyLinesCodec::new(4)).await;
         let decoded = decoded.iter().map(String::as_str).collect::<Vec<&str>>();
         assert_eq!(expected, decoded.as_slice());
     }
+
+    #[test]
+    fn discard_test() {
+        let mut codec = LossyLinesCodec::new(4);
+        let mut buf = BytesMut::from("abcdef");
+        let text = |line: Option<DecodedLine>| line.map(|line| line.text);
+        assert_eq!(
+            text(codec.decode(&mut buf).unwrap()).as_deref(),
+            Some("abcd")
+        );
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        // Discarded bytes are not buffered.
+        assert!(buf.is_empty());
+
+        buf.extend_from_slice(b"gh\nij");
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert_eq!(
+            text(codec.decode_eof(&mut buf).unwrap()).as_deref(),
+            Some("ij")
+        );
+    }
+
+    #[test]
+    fn redraw_test() {
+        let mut codec = LossyLinesCodec::default();
+        let line = |text: &str, redraw| {
+            Some(DecodedLine {
+                text: text.into(),
+                redraw,
+            })
+        };
+        let mut buf = BytesMut::from("foo\r\n10%\r20%\r");
+        assert_eq!(codec.decode(&mut buf).unwrap(), line("foo", false));
+        assert_eq!(codec.decode(&mut buf).unwrap(), line("10%", true));
+        // Carriage return at the end of buffer might be followed by a new line.
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+
+        buf.extend_from_slice(b"\nbar\r");
+        assert_eq!(codec.decode(&mut buf).unwrap(), line("20%", false));
+        assert_eq!(codec.decode(&mut buf).unwrap(), None);
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), line("bar", false));
+        assert_eq!(codec.decode_eof(&mut buf).unwrap(), None);
+    }
+
+    #[test_case(0, Some(10); "first restart")]
+    #[test_case(2, Some(40); "backoff doubled")]
+    #[test_case(3, None; "limit reached")]
+    fn restart_backoff_test(restarts: u32, expected: Option<u64>) {
+        let policy = RestartPolicy::default();
+        let expected = expected.map(Duration::from_secs);
+        assert_eq!(policy.backoff(restarts), expected);
+    }
+
+    #[test]
+    fn runtime_dir_test() {
+        let work_dir = std::env::temp_dir().join("ya-runtime-ai-runtime-dir-test");
+        let _ = std::fs::remove_dir_all(&work_dir);
+        let ctx = ProcessContext {
+            work_dir: work_dir.clone(),
+            ..Default::default()
+        };
+
+        assert_eq!(ctx.runtime_dir(None).unwrap(), work_dir);
+        assert!(work_dir.is_dir());
+
+        let dir = ctx.runtime_dir(Some("runtime/data".as_ref())).unwrap();
+        assert_eq!(dir, work_dir.join("runtime").join("data"));
+        assert!(dir.is_dir());
+
+        std::fs::remove_dir_all(&work_dir).unwrap();
+    }
 }
@@ -1,30 +1,34 @@
 pub(crate) mod config;
 
 mod monitor;
+mod request_policy;
 
 use self::config::Config;
 
-use super::Runtime;
+use super::{InferenceRequest, Runtime};
 
-use crate::process::{automatic::monitor::OutputMonitor, process_output};
-use anyhow::Context;
+use crate::benchmark::Workload;
+use crate::process::automatic::monitor::{OutputMonitor, RecentLines};
+use crate::process::process_output;
+use crate::process::ProcessContext;
+use crate::proxy::RequestFilter;
+use crate::self_test::{self, TestReport};
 use async_trait::async_trait;
 use tokio::{
     process::{Child, Command},
     sync::Mutex,
-    time::timeout,
 };
 
 use std::{
     path::PathBuf,
     process::{ExitStatus, Stdio},
+    rc::Rc,
     sync::Arc,
 };
 
 #[derive(Clone)]
 pub struct Automatic {
     child: Arc<Mutex<Child>>,
-    #[allow(dead_code)]
     output_monitor: Arc<monitor::OutputMonitor>,
     config: Config,
 }
@@ -33,22 +37,24 @@ pub struct Automatic {
 impl Runtime for Automatic {
     type CONFIG = Config;
 
-    async fn start(model: Option<PathBuf>, config: Self::CONFIG) -> anyhow::Result<Automatic> {
+    async fn start(
+        model: Option<PathBuf>,
+        config: Self::CONFIG,
+        ctx: ProcessContext,
+    ) -> anyhow::Result<Automatic> {
         log::info!("Building startup cmd. Config {config:?}");
-        let mut cmd = build_cmd(model, &config)?;
+        let mut cmd = build_cmd(model, &config, &ctx)?;
 
         log::info!("Spawning Automatic process");
         let mut child = cmd.kill_on_drop(true).spawn()?;
 
-        let output = process_output(&mut child)?;
+        let output = process_output(&mut child, &ctx.output, config.max_output_line_length)?;
 
         log::info!("Waiting for Automatic startup");
-        let output_monitor = timeout(
-            config.startup_timeout,
-            OutputMonitor::start(output, config.clone()),
-        )
-        .await
-        .context("Automatic startup timeout.")??;
+        let recent = RecentLines::new(config.error_output_lines);
+        let output_monitor = OutputMonitor::start(output, config.clone(), ctx, recent.clone())
+            .await
+            .map_err(|err| recent.attach(err))?;
 
         log::info!("Automatic has started");
         let child = Arc::new(Mutex::new(child));
@@ -79,9 +85,66 @@ impl Runtime for Automatic {
         log::debug!("Automatic process has stopped");
         res
     }
+
+    fn test(config: &Self::CONFIG, report: &mut TestReport) {
+        report.check("gpu", || self_test::gpu(config));
+        report.check("startup-script", || {
+            self_test::executable(&config.startup_script)
+        });
+        report.check_warning("api-port", || {
+            self_test::port_free(&config.api_host, config.api_port)
+        });
+    }
+
+    fn inference_request(_config: &Self::CONFIG) -> InferenceRequest {
+        InferenceRequest {
+            path: "sdapi/v1/txt2img".into(),
+            body: serde_json::json!({
+                "prompt": "a cat",
+                "steps": 1,
+                "width": 64,
+                "height": 64,
+            }),
+        }
+    }
+
+    fn benchmark_workload(config: &Self::CONFIG) -> Workload {
+        let benchmark = &config.benchmark;
+        Workload {
+            request: InferenceRequest {
+                path: "sdapi/v1/txt2img".into(),
+                body: serde_json::json!({
+                    "prompt": benchmark.prompt,
+                    "steps": benchmark.steps,
+                    "width": benchmark.width,
+                    "height": benchmark.height,
+                    "batch_size": benchmark.batch_size,
+                    "seed": 1,
+                }),
+            },
+            metric: "images-per-sec".into(),
+            results_per_request: benchmark.batch_size,
+        }
+    }
+
+    fn request_filter(config: &Self::CONFIG) -> Option<Rc<dyn RequestFilter>> {
+        Some(Rc::new(config.request_policy.clone()))
+    }
+
+    fn cancel_path(config: &Self::CONFIG) -> Option<String> {
+        Some(config.api_interrupt_path.clone())
+    }
+
+    fn recent_output(&self) -> Vec<String> {
+        self.output_monitor.recent_lines()
+    }
 }
 
-fn build_cmd(model: Option<PathBuf>, config: &Config) -> anyhow::Result<Command> {
+fn build_cmd(
+    model: Option<PathBuf>,
+    config: &Config,
+    ctx: &ProcessContext,
+) -> anyhow::Result<Command> {
     let script = super::find_file(&config.startup_script)?;
 
     let mut cmd = Command::new(script);
@@ -94,9 +157,17 @@ fn build_cmd(model: Option<PathBuf>, config: &Config) -> anyhow::Result<Command>
         log::warn!("No model arg");
     }
 
+    let work_dir = ctx.runtime_dir(config.work_dir.as_deref())?;
+    if let Some(data_dir_arg) = &config.data_dir_arg {
+        cmd.arg(data_dir_arg).arg(&work_dir);
+    }
+
+    config.env.apply(&mut cmd, ctx);
+
     cmd.stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .stdin(Stdio::null());
+        .stdin(Stdio::null())
+        .current_dir(&work_dir);
     Ok(cmd)
 }
 
@@ -1,8 +1,18 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use std::time::Duration;
 
 use serde::Deserialize;
 
-use crate::process::RuntimeConfig;
+use crate::counters::{EndpointClass, REQUESTS_COUNTER};
+use crate::host_monitor::HostMonitorConfig;
+use crate::offer_template::Capabilities;
+use crate::process::automatic::monitor::{LogLevel, MonitorRule, RuleAction};
+use crate::process::automatic::request_policy::RequestPolicy;
+use crate::process::env::EnvConfig;
+use crate::process::output_log::OutputLogConfig;
+use crate::process::{FailureReaction, RestartPolicy, RuntimeConfig, DEFAULT_MAX_LINE_LENGTH};
+use crate::proxy::{AuditConfig, Endpoint, EndpointAccess, EndpointTimeout, QueueConfig};
 
 #[derive(Deserialize, Clone, Debug)]
 #[serde(default)]
@@ -15,30 +25,244 @@ pub(crate) struct Config {
 
     pub api_shutdown_path: String,
 
+    pub api_interrupt_path: String,
+
     pub model_arg: String,
 
     pub additional_args: Vec<String>,
 
+    pub env: EnvConfig,
+
+    /// Working directory of Automatic process. Relative to activity work dir, which is the default.
+    /// Only the working directory moves. Automatic keeps writing outputs (images saved on
+    /// `save_images` requests, logs, configs) to its data dir, which is its install dir
+    /// unless `data_dir_arg` is set.
+    pub work_dir: Option<PathBuf>,
+
+    /// Automatic argument pointing its data (outputs, configs, temp files) to the working directory.
+    /// With `--data-dir` models, extensions and settings are looked up there too,
+    /// so it is not set by default.
+    pub data_dir_arg: Option<String>,
+
     // Monitor
+    /// Limit of Automatic startup time.
     #[serde(with = "humantime_serde")]
     pub startup_timeout: Duration,
 
+    /// Limit of time without Automatic output during startup.
+    #[serde(with = "humantime_serde")]
+    pub startup_inactivity_timeout: Duration,
+
     #[serde(with = "humantime_serde")]
     pub api_ping_delay: Duration,
 
-    pub monitored_startup_msg: String,
+    pub monitor_rules: Vec<MonitorRule>,
 
-    pub monitored_model_failure_msg: String,
+    /// Deprecated. Prefix of the line notifying Automatic startup. Replaces `ready` rules.
+    pub monitored_startup_msg: Option<String>,
 
-    pub monitored_msgs_w_trace_lvl: Vec<String>,
+    /// Deprecated. Prefix of the line notifying model loading failure.
+    /// Replaces `fail-startup` rules.
+    pub monitored_model_failure_msg: Option<String>,
+
+    /// Deprecated. Lines containing any of the messages are logged at trace level.
+    /// Replaces `log-level` rules with `trace` level.
+    pub monitored_msgs_w_trace_lvl: Option<Vec<String>>,
+
+    /// Limits of Automatic restarts on `fail-runtime` rules with `restart` reaction.
+    pub restart_policy: RestartPolicy,
+
+    /// Number of the last Automatic output lines attached to startup and runtime errors.
+    pub error_output_lines: usize,
+
+    /// Longer lines of Automatic output are truncated.
+    pub max_output_line_length: usize,
+
+    pub output_log: OutputLogConfig,
 
     pub gpu_uuid: Option<String>,
+
+    // GPU availability
+    pub max_gpu_utilization: Option<u32>,
+
+    pub min_free_gpu_memory_gib: Option<f32>,
+
+    pub host_monitor: Option<HostMonitorConfig>,
+
+    pub min_free_disk_space_gib: Option<f32>,
+
+    // Offer
+    pub capabilities: Capabilities,
+
+    pub benchmark: BenchmarkConfig,
+
+    // Proxy
+    pub endpoints: Vec<EndpointClass>,
+
+    pub access: EndpointAccess,
+
+    pub request_policy: RequestPolicy,
+
+    pub queue: QueueConfig,
+
+    pub timeouts: Vec<EndpointTimeout>,
+
+    pub audit: Option<AuditConfig>,
+
+    // Metrics
+    pub metrics_address: Option<SocketAddr>,
+}
+
+/// Workload of `benchmark` command.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub(crate) struct BenchmarkConfig {
+    pub prompt: String,
+
+    pub steps: u32,
+
+    pub width: u32,
+
+    pub height: u32,
+
+    pub batch_size: u32,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        Self {
+            prompt: "a photo of an astronaut riding a horse on mars".into(),
+            steps: 20,
+            width: 512,
+            height: 512,
+            batch_size: 1,
+        }
+    }
+}
+
+impl Config {
+    /// `monitor_rules` with deprecated `monitored_*` fields mapped into rules.
+    pub fn monitor_rules(&self) -> Vec<MonitorRule> {
+        let mut rules = self.monitor_rules.clone();
+        if let Some(msg) = &self.monitored_startup_msg {
+            deprecated("monitored_startup_msg", "ready");
+            let rule = MonitorRule::new(&format!("^{}", regex::escape(msg)), RuleAction::Ready);
+            replace_rules(
+                &mut rules,
+                |action| *action == RuleAction::Ready,
+                vec![rule],
+            );
+        }
+        if let Some(msg) = &self.monitored_model_failure_msg {
+            deprecated("monitored_model_failure_msg", "fail-startup");
+            let rule =
+                MonitorRule::new(&format!("^{}", regex::escape(msg)), RuleAction::FailStartup);
+            replace_rules(
+                &mut rules,
+                |action| *action == RuleAction::FailStartup,
+                vec![rule],
+            );
+        }
+        if let Some(msgs) = &self.monitored_msgs_w_trace_lvl {
+            deprecated("monitored_msgs_w_trace_lvl", "log-level");
+            let trace = RuleAction::LogLevel {
+                level: LogLevel::Trace,
+            };
+            let aliases = msgs
+                .iter()
+                .map(|msg| MonitorRule::new(&regex::escape(msg), trace.clone()))
+                .collect();
+            replace_rules(&mut rules, |action| *action == trace, aliases);
+        }
+        rules
+    }
+}
+
+fn deprecated(field: &str, action: &str) {
+    log::warn!("Config `{field}` is deprecated. Use `monitor_rules` with `{action}` action.");
+}
+
+/// Puts `aliases` in front of `rules`, removing rules with `replaced` actions.
+fn replace_rules(
+    rules: &mut Vec<MonitorRule>,
+    replaced: impl Fn(&RuleAction) -> bool,
+    aliases: Vec<MonitorRule>,
+) {
+    rules.retain(|rule| !replaced(&rule.action));
+    rules.splice(0..0, aliases);
 }
 
 impl RuntimeConfig for Config {
     fn gpu_uuid(&self) -> Option<String> {
         self.gpu_uuid.clone()
     }
+
+    fn api_url(&self) -> String {
+        format!("http://{}:{}/", self.api_host, self.api_port)
+    }
+
+    /// `max_resolution` defaults to the one accepted by `request_policy`.
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = self.capabilities.clone();
+        if capabilities.max_resolution.is_none() {
+            let policy = &self.request_policy;
+            capabilities.max_resolution = policy
+                .max_width
+                .into_iter()
+                .chain(policy.max_height)
+                .min()
+                .map(|max| u32::try_from(max).unwrap_or(u32::MAX));
+        }
+        capabilities
+    }
+
+    fn max_gpu_utilization(&self) -> Option<u32> {
+        self.max_gpu_utilization
+    }
+
+    fn min_free_gpu_memory_gib(&self) -> Option<f32> {
+        self.min_free_gpu_memory_gib
+    }
+
+    fn host_monitor(&self) -> Option<HostMonitorConfig> {
+        self.host_monitor.clone()
+    }
+
+    fn min_free_disk_space_gib(&self) -> Option<f32> {
+        self.min_free_disk_space_gib
+    }
+
+    fn endpoints(&self) -> Vec<EndpointClass> {
+        self.endpoints.clone()
+    }
+
+    fn access(&self) -> EndpointAccess {
+        self.access.clone()
+    }
+
+    fn queue(&self) -> QueueConfig {
+        self.queue.clone()
+    }
+
+    fn timeouts(&self) -> Vec<EndpointTimeout> {
+        self.timeouts.clone()
+    }
+
+    fn audit(&self) -> Option<AuditConfig> {
+        self.audit.clone()
+    }
+
+    fn metrics_address(&self) -> Option<SocketAddr> {
+        self.metrics_address
+    }
+
+    fn output_log(&self) -> OutputLogConfig {
+        self.output_log.clone()
+    }
+
+    fn restart_policy(&self) -> RestartPolicy {
+        self.restart_policy.clone()
+    }
 }
 
 impl Default for Config {
@@ -48,21 +272,135 @@ impl Default for Config {
             api_port: 7861,
             api_host: "localhost".into(),
             api_shutdown_path: "sdapi/v1/server-kill".into(),
+            api_interrupt_path: "sdapi/v1/interrupt".into(),
             model_arg: "--ckpt".into(),
             additional_args: vec![
                 "--skip-torch-cuda-test".into(),
                 "--skip-python-version-check".into(),
                 "--skip-version-check".into(),
             ],
-            startup_timeout: Duration::from_secs(90),
+            env: EnvConfig::default(),
+            work_dir: None,
+            data_dir_arg: None,
+            startup_timeout: Duration::from_secs(30 * 60),
+            startup_inactivity_timeout: Duration::from_secs(120),
             api_ping_delay: Duration::from_millis(997),
-            monitored_startup_msg: "Model loaded in ".into(),
-            monitored_model_failure_msg: "Stable diffusion model failed to load".into(),
-            monitored_msgs_w_trace_lvl: vec![
+            monitor_rules: vec![
+                MonitorRule::new("^Model loaded in ", RuleAction::Ready),
+                MonitorRule::new(
+                    r"^Model loaded in ([0-9.]+)s",
+                    RuleAction::Metric {
+                        name: "model_load_sec".into(),
+                    },
+                ),
+                MonitorRule::new(
+                    "^Stable diffusion model failed to load",
+                    RuleAction::FailStartup,
+                ),
+                MonitorRule::new(
+                    "CUDA out of memory",
+                    RuleAction::FailRuntime {
+                        reaction: FailureReaction::Report,
+                    },
+                ),
                 // log generated by API ping task
-                "\"GET / HTTP/1.1\" 404 Not Found".into(),
+                MonitorRule::new(
+                    r#""GET / HTTP/1\.1" 404 Not Found"#,
+                    RuleAction::LogLevel {
+                        level: LogLevel::Trace,
+                    },
+                ),
             ],
+            monitored_startup_msg: None,
+            monitored_model_failure_msg: None,
+            monitored_msgs_w_trace_lvl: None,
+            restart_policy: RestartPolicy::default(),
+            error_output_lines: 20,
+            max_output_line_length: DEFAULT_MAX_LINE_LENGTH,
+            output_log: OutputLogConfig::default(),
             gpu_uuid: None,
+            max_gpu_utilization: None,
+            min_free_gpu_memory_gib: None,
+            host_monitor: None,
+            min_free_disk_space_gib: Some(10.0),
+            capabilities: Capabilities {
+                api: vec!["sdapi/v1".into()],
+                model_formats: vec!["safetensors".into(), "ckpt".into()],
+                max_resolution: None,
+            },
+            benchmark: BenchmarkConfig::default(),
+            endpoints: ["txt2img", "img2img"]
+                .into_iter()
+                .map(|endpoint| EndpointClass {
+                    endpoint: Endpoint {
+                        method: Some("POST".into()),
+                        path: format!("sdapi/v1/{endpoint}"),
+                    },
+                    counter: REQUESTS_COUNTER.into(),
+                    weight: 1.0,
+                })
+                .collect(),
+            access: EndpointAccess {
+                allow: vec![Endpoint {
+                    method: None,
+                    path: "sdapi/v1/.*".into(),
+                }],
+                deny: vec![
+                    Endpoint {
+                        method: None,
+                        path: "sdapi/v1/server-(kill|restart|stop)".into(),
+                    },
+                    Endpoint {
+                        method: Some("POST".into()),
+                        path: "sdapi/v1/(options|cmd-flags)".into(),
+                    },
+                    Endpoint {
+                        method: Some("POST".into()),
+                        path: "sdapi/v1/(reload|unload)-checkpoint".into(),
+                    },
+                    // Files persisting across activities and backend state changes
+                    Endpoint {
+                        method: None,
+                        path: "sdapi/v1/(create|train)/.*".into(),
+                    },
+                    Endpoint {
+                        method: None,
+                        path: "sdapi/v1/preprocess".into(),
+                    },
+                    Endpoint {
+                        method: None,
+                        path: "sdapi/v1/refresh-.*".into(),
+                    },
+                ],
+            },
+            request_policy: RequestPolicy::default(),
+            // Automatic generates images one by one
+            queue: QueueConfig {
+                max_in_flight: Some(1),
+                endpoints: vec![Endpoint {
+                    method: Some("POST".into()),
+                    path: "sdapi/v1/(txt2img|img2img)".into(),
+                }],
+                ..Default::default()
+            },
+            timeouts: vec![
+                EndpointTimeout {
+                    endpoint: Endpoint {
+                        method: Some("POST".into()),
+                        path: "sdapi/v1/(txt2img|img2img)".into(),
+                    },
+                    timeout: Duration::from_secs(600),
+                },
+                EndpointTimeout {
+                    endpoint: Endpoint {
+                        method: None,
+                        path: ".*".into(),
+                    },
+                    timeout: Duration::from_secs(120),
+                },
+            ],
+            audit: None,
+            metrics_address: None,
         }
     }
 }
@@ -71,7 +409,12 @@ impl Default for Config {
 mod config_tests {
     use std::{fs, path::PathBuf};
 
+    use serde_json::json;
+    use test_case::test_case;
+
     use super::Config;
+    use crate::process::automatic::monitor::{LogLevel, RuleAction};
+    use crate::process::RuntimeConfig;
 
     #[test]
     fn config_test() {
@@ -80,4 +423,75 @@ mod config_tests {
         let config = fs::read_to_string(path).unwrap();
         serde_json::from_str::<Config>(&config).expect("Can parse config");
     }
+
+    #[test_case("POST", "/sdapi/v1/create/embedding"; "create")]
+    #[test_case("POST", "/sdapi/v1/train/hypernetwork"; "train")]
+    #[test_case("POST", "/sdapi/v1/preprocess"; "preprocess")]
+    #[test_case("POST", "/sdapi/v1/refresh-checkpoints"; "refresh")]
+    #[test_case("POST", "/sdapi/v1/server-kill"; "server kill")]
+    fn default_denied_test(method: &str, path: &str) {
+        let deny = Config::default().access.deny;
+        assert!(deny
+            .iter()
+            .any(|endpoint| endpoint.matcher().unwrap().matches(method, path)));
+    }
+
+    #[test]
+    fn max_resolution_test() {
+        let mut config = Config::default();
+        assert_eq!(config.capabilities().max_resolution, Some(2048));
+
+        config.request_policy.max_height = Some(1024);
+        assert_eq!(config.capabilities().max_resolution, Some(1024));
+
+        config.capabilities.max_resolution = Some(512);
+        assert_eq!(config.capabilities().max_resolution, Some(512));
+
+        config.capabilities.max_resolution = None;
+        config.request_policy.max_width = None;
+        config.request_policy.max_height = None;
+        assert_eq!(config.capabilities().max_resolution, None);
+    }
+
+    #[test]
+    fn deprecated_monitor_fields_test() {
+        let config: Config = serde_json::from_value(json!({
+            "monitored_startup_msg": "Running on (local)",
+            "monitored_model_failure_msg": "Failed",
+            "monitored_msgs_w_trace_lvl": ["GET /", "ping"]
+        }))
+        .unwrap();
+        let rules = config.monitor_rules();
+        let rules = rules
+            .iter()
+            .map(|rule| (rule.pattern.as_str(), rule.action.clone()))
+            .collect::<Vec<_>>();
+        let trace = RuleAction::LogLevel {
+            level: LogLevel::Trace,
+        };
+        assert_eq!(
+            rules,
+            vec![
+                ("GET /", trace.clone()),
+                ("ping", trace),
+                ("^Failed", RuleAction::FailStartup),
+                (r"^Running on \(local\)", RuleAction::Ready),
+                (
+                    r"^Model loaded in ([0-9.]+)s",
+                    RuleAction::Metric {
+                        name: "model_load_sec".into()
+                    }
+                ),
+                (
+                    "CUDA out of memory",
+                    RuleAction::FailRuntime {
+                        reaction: Default::default()
+                    }
+                ),
+            ]
+        );
+
+        let config = Config::default();
+        assert_eq!(config.monitor_rules().len(), config.monitor_rules.len());
+    }
 }
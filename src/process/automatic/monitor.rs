@@ -1,31 +1,223 @@
-use crate::process::OutputLines;
+use crate::process::{
+    FailureReaction, OutputLine, OutputLines, OutputStream, ProcessContext, RuntimeFailure,
+};
 
 use super::*;
 
-use std::sync::Arc;
+use anyhow::Context;
+use regex::{Captures, Regex};
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex as StdMutex};
 use tokio::sync::oneshot::{self};
+use tokio::sync::watch;
 use tokio::task::JoinHandle;
+use tokio::time::{sleep, sleep_until, Instant};
 use tokio_stream::StreamExt;
 
+/// Action taken on Automatic output line matching the `pattern`.
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct MonitorRule {
+    /// Regex matching (part of) the line.
+    pub pattern: String,
+    /// Output stream of the line. Matches both streams when not set.
+    #[serde(default)]
+    pub stream: Option<OutputStream>,
+    #[serde(flatten)]
+    pub action: RuleAction,
+}
+
+impl MonitorRule {
+    pub fn new(pattern: &str, action: RuleAction) -> Self {
+        Self {
+            pattern: pattern.into(),
+            stream: None,
+            action,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub(crate) enum RuleAction {
+    /// Automatic has started.
+    Ready,
+    /// Automatic failed to start.
+    FailStartup,
+    /// Automatic failed after startup.
+    FailRuntime {
+        #[serde(default)]
+        reaction: FailureReaction,
+    },
+    /// Logs the line with `level` (first matching rule wins).
+    LogLevel { level: LogLevel },
+    /// Sets runtime metric `name` to number captured by the first group of the pattern.
+    Metric { name: String },
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl From<LogLevel> for log::Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => log::Level::Error,
+            LogLevel::Warn => log::Level::Warn,
+            LogLevel::Info => log::Level::Info,
+            LogLevel::Debug => log::Level::Debug,
+            LogLevel::Trace => log::Level::Trace,
+        }
+    }
+}
+
+struct Rule {
+    pattern: Regex,
+    stream: Option<OutputStream>,
+    action: RuleAction,
+}
+
+struct Rules(Vec<Rule>);
+
+impl Rules {
+    fn new(rules: &[MonitorRule]) -> anyhow::Result<Self> {
+        let rules = rules
+            .iter()
+            .map(|rule| {
+                let pattern = Regex::new(&rule.pattern).map_err(|err| {
+                    anyhow::anyhow!("Invalid monitor rule pattern {}. Err {err}", rule.pattern)
+                })?;
+                Ok(Rule {
+                    pattern,
+                    stream: rule.stream,
+                    action: rule.action.clone(),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self(rules))
+    }
+
+    /// Actions of rules matching the line (in rules order) with pattern captures.
+    fn matching<'a>(
+        &'a self,
+        line: &'a OutputLine,
+    ) -> impl Iterator<Item = (&'a RuleAction, Captures<'a>)> {
+        self.0
+            .iter()
+            .filter(|rule| rule.stream.map_or(true, |stream| stream == line.stream))
+            .filter_map(|rule| Some((&rule.action, rule.pattern.captures(&line.text)?)))
+    }
+}
+
+/// Ring buffer of the last lines of Automatic output.
+/// Progress bar redraw is replaced by the next line of its stream.
+#[derive(Clone)]
+pub(super) struct RecentLines {
+    capacity: usize,
+    lines: Arc<StdMutex<VecDeque<OutputLine>>>,
+}
+
+impl RecentLines {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            lines: Default::default(),
+        }
+    }
+
+    fn push(&self, line: &OutputLine) {
+        let mut lines = self.lines.lock().unwrap();
+        let previous = lines
+            .iter()
+            .rposition(|recent| recent.stream == line.stream);
+        if let Some(previous) = previous.filter(|&index| lines[index].redraw) {
+            lines.remove(previous);
+        }
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        if self.capacity > 0 {
+            lines.push_back(line.clone());
+        }
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        let lines = self.lines.lock().unwrap();
+        lines.iter().map(|line| line.text.clone()).collect()
+    }
+
+    /// Appends recent lines to the error message.
+    pub fn attach(&self, err: anyhow::Error) -> anyhow::Error {
+        let lines = self.lines();
+        if lines.is_empty() {
+            return err;
+        }
+        anyhow::anyhow!("{err:#}\nLast output lines:\n{}", lines.join("\n"))
+    }
+}
+
 pub(super) struct OutputMonitor {
     #[allow(dead_code)]
     output_task: Arc<JoinHandle<()>>,
+    recent: RecentLines,
 }
 
 impl OutputMonitor {
-    pub async fn start(lines: OutputLines, config: Config) -> anyhow::Result<Self> {
-        let (on_startup_tx, on_startup_rx) = oneshot::channel();
-        let output_handler = OutputHandler::LookingForStartup {
-            on_startup_tx,
-            config: config.clone(),
+    pub async fn start(
+        lines: OutputLines,
+        config: Config,
+        ctx: ProcessContext,
+        recent: RecentLines,
+    ) -> anyhow::Result<Self> {
+        let (on_startup_tx, mut on_startup_rx) = oneshot::channel();
+        let (activity_tx, mut activity_rx) = watch::channel(());
+        let output_handler = OutputHandler {
+            rules: Rules::new(&config.monitor_rules())?,
+            ctx,
+            recent: recent.clone(),
+            activity_tx,
+            on_startup_tx: Some(on_startup_tx),
         };
         let output_task = Arc::new(spawn_output_monitoring(lines, output_handler));
 
-        on_startup_rx
-            .await
-            .context("Automatic failed on startup")??;
+        // Every output line (including progress bar redraws) resets inactivity timeout.
+        let deadline = Instant::now() + config.startup_timeout;
+        loop {
+            tokio::select! {
+                result = &mut on_startup_rx => {
+                    result.context("Automatic failed on startup")??;
+                    break;
+                }
+                Ok(()) = activity_rx.changed() => {}
+                _ = sleep(config.startup_inactivity_timeout) => {
+                    anyhow::bail!(
+                        "Automatic startup timeout. No output for {}",
+                        humantime::format_duration(config.startup_inactivity_timeout)
+                    );
+                }
+                _ = sleep_until(deadline) => {
+                    anyhow::bail!(
+                        "Automatic startup timeout. Not started in {}",
+                        humantime::format_duration(config.startup_timeout)
+                    );
+                }
+            }
+        }
 
-        Ok(Self { output_task })
+        Ok(Self {
+            output_task,
+            recent,
+        })
+    }
+
+    pub fn recent_lines(&self) -> Vec<String> {
+        self.recent.lines()
     }
 }
 
@@ -36,73 +228,274 @@ fn spawn_output_monitoring(
     tokio::spawn(async move {
         while let Some(line) = lines.next().await {
             match line {
-                Ok(line) => {
-                    output_handler = output_handler.handle(line);
-                }
+                Ok(line) => output_handler.handle(line),
                 Err(err) => log::error!("Failed to read line. Err {err}"),
             }
         }
     })
 }
 
-enum OutputHandler {
-    LookingForStartup {
-        //TODO create a custom error type?
-        on_startup_tx: oneshot::Sender<anyhow::Result<()>>,
-        config: Config,
-    },
-    Logging {
-        config: Config,
-    },
+struct OutputHandler {
+    rules: Rules,
+    ctx: ProcessContext,
+    recent: RecentLines,
+    /// Notified on every line.
+    activity_tx: watch::Sender<()>,
+    /// Set while looking for startup.
+    //TODO create a custom error type?
+    on_startup_tx: Option<oneshot::Sender<anyhow::Result<()>>>,
 }
 
 impl OutputHandler {
-    fn handle(self, line: String) -> Self {
-        self.log_process_output(&line);
-        match self {
-            Self::LookingForStartup {
-                on_startup_tx,
-                config,
-            } => {
-                if line.starts_with(&config.monitored_startup_msg) {
-                    if on_startup_tx.send(Ok(())).is_err() {
-                        log::error!("Failed to notify on startup");
+    fn handle(&mut self, line: OutputLine) {
+        self.recent.push(&line);
+        self.activity_tx.send_replace(());
+        let mut level = None;
+        for (action, captures) in self.rules.matching(&line) {
+            match action {
+                RuleAction::Ready => {
+                    if let Some(on_startup_tx) = self.on_startup_tx.take() {
+                        if on_startup_tx.send(Ok(())).is_err() {
+                            log::error!("Failed to notify on startup");
+                        }
                     }
-                    return Self::Logging { config };
-                } else if line.starts_with(&config.monitored_model_failure_msg) {
-                    if on_startup_tx
-                        .send(Err(anyhow::anyhow!("Automatic failed to load model")))
-                        .is_err()
-                    {
-                        log::error!("Failed to notify on model loading failure");
+                }
+                RuleAction::FailStartup => {
+                    if let Some(on_startup_tx) = self.on_startup_tx.take() {
+                        log::warn!("Automatic failed to start");
+                        let err = anyhow::anyhow!("Automatic failed to start: {}", line.text);
+                        if on_startup_tx.send(Err(err)).is_err() {
+                            log::error!("Failed to notify on startup failure");
+                        }
+                    }
+                }
+                RuleAction::FailRuntime { reaction } => {
+                    if self.on_startup_tx.is_none() {
+                        log::error!("Automatic failure: {}", line.text);
+                        self.ctx.report_failure(RuntimeFailure {
+                            reaction: *reaction,
+                            message: line.text.clone(),
+                        });
                     }
-                    log::warn!("Failed to load model");
-                    return Self::Logging { config };
                 }
-                Self::LookingForStartup {
-                    on_startup_tx,
-                    config,
+                RuleAction::LogLevel { level: rule_level } => {
+                    level = level.or(Some(log::Level::from(*rule_level)));
+                }
+                RuleAction::Metric { name } => {
+                    let value = captures.get(1).map(|value| value.as_str().parse::<f64>());
+                    match value {
+                        Some(Ok(value)) => self.ctx.metrics.set_runtime_value(name, value),
+                        _ => log::warn!("No {name} metric value in line: {}", line.text),
+                    }
                 }
             }
-            // Logging
-            _ => self,
         }
+        log_process_output(&line, level);
     }
+}
+
+/// Lines of both streams are logged at debug unless a `log-level` rule matches.
+/// Python libraries write progress bars and most of their logs to stderr.
+fn log_process_output(line: &OutputLine, level: Option<log::Level>) {
+    let prefix = match line.stream {
+        OutputStream::Stdout => ">",
+        OutputStream::Stderr => "!>",
+    };
+    log::log!(level.unwrap_or(log::Level::Debug), "{prefix} {}", line.text);
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use serde_json::json;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
 
-    fn log_process_output(&self, line: &str) {
-        let config = match self {
-            Self::Logging { config } => config,
-            Self::LookingForStartup {
-                on_startup_tx: _,
-                config,
-            } => config,
+    use super::*;
+
+    fn line(stream: OutputStream, text: &str) -> OutputLine {
+        OutputLine {
+            stream,
+            timestamp: Utc::now(),
+            text: text.into(),
+            redraw: false,
+        }
+    }
+
+    fn redraw(stream: OutputStream, text: &str) -> OutputLine {
+        OutputLine {
+            redraw: true,
+            ..line(stream, text)
+        }
+    }
+
+    #[test]
+    fn default_rules_test() {
+        let rules = Rules::new(&Config::default().monitor_rules).unwrap();
+        let actions = |line: &OutputLine| {
+            rules
+                .matching(line)
+                .map(|(action, _)| action.clone())
+                .collect::<Vec<_>>()
         };
-        for message in &config.monitored_msgs_w_trace_lvl {
-            if line.contains(message) {
-                log::trace!("> {line}");
-                return;
-            }
+
+        let loaded = line(
+            OutputStream::Stdout,
+            "Model loaded in 5.2s (load weights from disk: 0.6s)",
+        );
+        assert_eq!(
+            actions(&loaded),
+            vec![
+                RuleAction::Ready,
+                RuleAction::Metric {
+                    name: "model_load_sec".into()
+                }
+            ]
+        );
+        let failed = line(
+            OutputStream::Stderr,
+            "Stable diffusion model failed to load",
+        );
+        assert_eq!(actions(&failed), vec![RuleAction::FailStartup]);
+        let ping = line(
+            OutputStream::Stderr,
+            "INFO: 127.0.0.1:1234 - \"GET / HTTP/1.1\" 404 Not Found",
+        );
+        assert_eq!(
+            actions(&ping),
+            vec![RuleAction::LogLevel {
+                level: LogLevel::Trace
+            }]
+        );
+        assert!(actions(&line(OutputStream::Stdout, "Loading weights")).is_empty());
+    }
+
+    #[test]
+    fn rules_test() {
+        let rules: Vec<MonitorRule> = serde_json::from_value(json!([
+            { "pattern": "^Ready", "stream": "stdout", "action": "ready" },
+            { "pattern": "VRAM: ([0-9.]+) GiB", "action": "metric", "name": "vram_gib" },
+            { "pattern": "out of memory", "action": "fail-runtime", "reaction": "restart" },
+            { "pattern": "Traceback", "action": "fail-runtime" }
+        ]))
+        .unwrap();
+        let (failures_tx, mut failures_rx) = mpsc::unbounded_channel();
+        let ctx = ProcessContext {
+            failures: Some(failures_tx),
+            ..Default::default()
+        };
+        let (on_startup_tx, mut on_startup_rx) = oneshot::channel();
+        let mut handler = OutputHandler {
+            rules: Rules::new(&rules).unwrap(),
+            ctx: ctx.clone(),
+            recent: RecentLines::new(2),
+            activity_tx: watch::channel(()).0,
+            on_startup_tx: Some(on_startup_tx),
+        };
+
+        handler.handle(line(OutputStream::Stderr, "Ready"));
+        assert!(on_startup_rx.try_recv().is_err());
+        handler.handle(line(OutputStream::Stdout, "Ready"));
+        assert!(on_startup_rx.try_recv().unwrap().is_ok());
+
+        handler.handle(line(OutputStream::Stdout, "VRAM: 6.5 GiB"));
+        assert!(ctx
+            .metrics
+            .render(None)
+            .contains("ya_runtime_ai_runtime_value{name=\"vram_gib\"} 6.5"));
+
+        handler.handle(line(OutputStream::Stderr, "CUDA out of memory"));
+        handler.handle(line(
+            OutputStream::Stderr,
+            "Traceback (most recent call last):",
+        ));
+        let failure = failures_rx.try_recv().unwrap();
+        assert_eq!(failure.reaction, FailureReaction::Restart);
+        assert_eq!(failure.message, "CUDA out of memory");
+        let failure = failures_rx.try_recv().unwrap();
+        assert_eq!(failure.reaction, FailureReaction::Report);
+
+        assert!(Rules::new(&[MonitorRule::new("(", RuleAction::Ready)]).is_err());
+    }
+
+    #[test]
+    fn recent_lines_test() {
+        let recent = RecentLines::new(2);
+        let err = recent.attach(anyhow::anyhow!("Automatic startup timeout."));
+        assert_eq!(err.to_string(), "Automatic startup timeout.");
+
+        for text in ["one", "two", "three"] {
+            recent.push(&line(OutputStream::Stdout, text));
+        }
+        assert_eq!(recent.lines(), vec!["two", "three"]);
+        let err = recent.attach(anyhow::anyhow!("Automatic startup timeout."));
+        assert_eq!(
+            err.to_string(),
+            "Automatic startup timeout.\nLast output lines:\ntwo\nthree"
+        );
+        assert!(RecentLines::new(0).lines().is_empty());
+    }
+
+    #[test]
+    fn recent_redraws_test() {
+        let recent = RecentLines::new(3);
+        recent.push(&line(OutputStream::Stderr, "Loading model"));
+        for text in ["10%", "50%", "90%"] {
+            recent.push(&redraw(OutputStream::Stderr, text));
+            recent.push(&line(OutputStream::Stdout, "Loading weights"));
         }
-        log::debug!("> {line}");
+        assert_eq!(
+            recent.lines(),
+            vec!["Loading weights", "90%", "Loading weights"]
+        );
+
+        recent.push(&line(OutputStream::Stderr, "100%"));
+        recent.push(&line(OutputStream::Stderr, "Traceback"));
+        assert_eq!(recent.lines(), vec!["Loading weights", "100%", "Traceback"]);
+    }
+
+    fn delayed_lines(delay: Duration, texts: Vec<&'static str>) -> OutputLines {
+        Box::pin(async_stream::stream! {
+            for text in texts {
+                tokio::time::sleep(delay).await;
+                yield Ok::<_, anyhow::Error>(line(OutputStream::Stdout, text));
+            }
+            futures::future::pending::<()>().await;
+        })
+    }
+
+    async fn start(lines: OutputLines, startup_timeout: Duration) -> anyhow::Result<OutputMonitor> {
+        let config = Config {
+            startup_timeout,
+            startup_inactivity_timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let recent = RecentLines::new(1);
+        OutputMonitor::start(lines, config, ProcessContext::default(), recent).await
+    }
+
+    #[tokio::test]
+    async fn startup_timeout_test() {
+        let step = Duration::from_millis(40);
+        let mut progress = vec!["Installing"; 5];
+        progress.push("Model loaded in 1.0s");
+        let started = start(delayed_lines(step, progress), Duration::from_secs(10)).await;
+        assert!(started.is_ok());
+
+        let silent = start(
+            delayed_lines(step, vec!["Installing"]),
+            Duration::from_secs(10),
+        )
+        .await;
+        let err = silent.err().unwrap().to_string();
+        assert!(err.contains("No output for 100ms"), "{err}");
+
+        let endless = delayed_lines(Duration::from_millis(20), vec!["Installing"; 100]);
+        let err = start(endless, Duration::from_millis(200))
+            .await
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("Not started in 200ms"), "{err}");
     }
 }
@@ -0,0 +1,275 @@
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::proxy::{request_path, RequestFilter};
+
+/// Generation endpoints which request bodies are checked.
+const GENERATION_PATHS: &[&str] = &["sdapi/v1/txt2img", "sdapi/v1/img2img"];
+
+/// Automatic defaults of parameters missing in request.
+const DEFAULT_SIZE: f64 = 512.0;
+const DEFAULT_HR_SCALE: f64 = 2.0;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub(crate) enum PolicyMode {
+    /// Requests exceeding limits are rejected.
+    #[default]
+    Reject,
+    /// Parameters exceeding limits are clamped and forbidden fields removed.
+    Clamp,
+}
+
+/// Limits of txt2img and img2img request parameters.
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub(crate) struct RequestPolicy {
+    pub mode: PolicyMode,
+
+    /// Maximum image width, also after hires upscale.
+    pub max_width: Option<u64>,
+
+    /// Maximum image height, also after hires upscale.
+    pub max_height: Option<u64>,
+
+    pub max_steps: Option<u64>,
+
+    pub max_batch_size: Option<u64>,
+
+    pub max_n_iter: Option<u64>,
+
+    /// Request fields not allowed to be set by requestors.
+    pub forbidden_fields: Vec<String>,
+}
+
+impl Default for RequestPolicy {
+    fn default() -> Self {
+        Self {
+            mode: PolicyMode::default(),
+            max_width: Some(2048),
+            max_height: Some(2048),
+            max_steps: Some(150),
+            max_batch_size: Some(8),
+            max_n_iter: Some(8),
+            forbidden_fields: vec![
+                "override_settings".into(),
+                "script_name".into(),
+                "script_args".into(),
+            ],
+        }
+    }
+}
+
+impl RequestPolicy {
+    /// Checks (or clamps) request parameters. Returns error message when request is rejected.
+    fn apply(&self, request: &mut Map<String, Value>) -> Result<(), String> {
+        for field in &self.forbidden_fields {
+            if request.contains_key(field) {
+                match self.mode {
+                    PolicyMode::Reject => return Err(format!("Field `{field}` is not allowed")),
+                    PolicyMode::Clamp => {
+                        request.remove(field);
+                    }
+                }
+            }
+        }
+
+        let limits = [
+            ("width", self.max_width),
+            ("height", self.max_height),
+            ("hr_resize_x", self.max_width),
+            ("hr_resize_y", self.max_height),
+            ("steps", self.max_steps),
+            ("hr_second_pass_steps", self.max_steps),
+            ("batch_size", self.max_batch_size),
+            ("n_iter", self.max_n_iter),
+        ];
+        for (field, max) in limits {
+            if let Some(max) = max {
+                self.limit(request, field, max)?;
+            }
+        }
+
+        let enable_hr = request.get("enable_hr").and_then(Value::as_bool);
+        let hr_resize = [
+            number(request, "hr_resize_x", 0.0)?,
+            number(request, "hr_resize_y", 0.0)?,
+        ];
+        // Automatic ignores `hr_scale` when both hires dimensions (limited above) are set.
+        if enable_hr == Some(true) && hr_resize.contains(&0.0) {
+            let hr_scale = number(request, "hr_scale", DEFAULT_HR_SCALE)?;
+            let dimensions = [
+                (number(request, "width", DEFAULT_SIZE)?, self.max_width),
+                (number(request, "height", DEFAULT_SIZE)?, self.max_height),
+            ];
+            let max_scale = dimensions
+                .into_iter()
+                .filter_map(|(size, max)| Some(max? as f64 / size))
+                .fold(f64::INFINITY, f64::min);
+            if hr_scale > max_scale {
+                match self.mode {
+                    PolicyMode::Reject => {
+                        return Err(format!(
+                            "Field `hr_scale` value {hr_scale} exceeds limit {max_scale:.2}"
+                        ))
+                    }
+                    PolicyMode::Clamp => {
+                        request.insert("hr_scale".into(), max_scale.into());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn limit(&self, request: &mut Map<String, Value>, field: &str, max: u64) -> Result<(), String> {
+        let Some(value) = request.get(field) else {
+            return Ok(());
+        };
+        let Some(value) = value.as_f64() else {
+            return Err(format!("Field `{field}` has to be a number"));
+        };
+        if value > max as f64 {
+            match self.mode {
+                PolicyMode::Reject => {
+                    return Err(format!("Field `{field}` value {value} exceeds limit {max}"))
+                }
+                PolicyMode::Clamp => {
+                    request.insert(field.into(), max.into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Numeric `field` of the request or Automatic `default` when it is missing.
+fn number(request: &Map<String, Value>, field: &str, default: f64) -> Result<f64, String> {
+    match request.get(field) {
+        Some(value) => value
+            .as_f64()
+            .ok_or_else(|| format!("Field `{field}` has to be a number")),
+        None => Ok(default),
+    }
+}
+
+impl RequestFilter for RequestPolicy {
+    fn filter(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>, String> {
+        if !method.eq_ignore_ascii_case("POST") || !GENERATION_PATHS.contains(&request_path(path)) {
+            return Ok(body);
+        }
+        let body = body.unwrap_or_default();
+        let mut request = match serde_json::from_slice(&body) {
+            Ok(Value::Object(request)) => request,
+            Ok(_) => return Err("Request body has to be a JSON object".into()),
+            Err(err) => return Err(format!("Invalid request body. Err {err}")),
+        };
+        self.apply(&mut request)?;
+        serde_json::to_vec(&request)
+            .map(Some)
+            .map_err(|err| format!("Failed to serialize request body. Err {err}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use test_case::test_case;
+
+    use super::*;
+    use crate::proxy::canonical_path;
+
+    /// Filters request with path canonicalized like by the proxy.
+    fn filter(policy: &RequestPolicy, path: &str, body: Value) -> Result<Value, String> {
+        let body = serde_json::to_vec(&body).unwrap();
+        let body = policy
+            .filter("POST", &canonical_path(path)?, Some(body))?
+            .unwrap();
+        Ok(serde_json::from_slice(&body).unwrap())
+    }
+
+    #[test_case(json!({ "prompt": "cat", "width": 512, "height": 512, "steps": 20 }); "within limits")]
+    #[test_case(json!({ "prompt": "cat", "enable_hr": true, "width": 512, "hr_scale": 2 }); "hires within limits")]
+    #[test_case(json!({ "enable_hr": true, "width": 2048, "height": 2048, "hr_resize_x": 2048, "hr_resize_y": 2048 }); "hires resize")]
+    fn accepted_test(request: Value) {
+        let policy = RequestPolicy::default();
+        assert_eq!(
+            filter(&policy, "/sdapi/v1/txt2img", request.clone()),
+            Ok(request)
+        );
+    }
+
+    #[test_case(json!({ "width": 8192 }); "width")]
+    #[test_case(json!({ "steps": 500 }); "steps")]
+    #[test_case(json!({ "batch_size": 64 }); "batch size")]
+    #[test_case(json!({ "steps": "many" }); "not a number")]
+    #[test_case(json!({ "enable_hr": true, "width": 1024, "hr_scale": 4 }); "hires scale")]
+    #[test_case(json!({ "enable_hr": true, "width": 2048, "height": 2048 }); "hires default scale")]
+    #[test_case(json!({ "enable_hr": true, "hr_scale": 16 }); "hires default size")]
+    #[test_case(json!({ "enable_hr": true, "hr_scale": "big" }); "hires scale not a number")]
+    #[test_case(json!({ "hr_second_pass_steps": 500 }); "hires steps")]
+    #[test_case(json!({ "override_settings": { "sd_model_checkpoint": "other" } }); "forbidden field")]
+    #[test_case(json!(["not", "object"]); "not an object")]
+    fn rejected_test(request: Value) {
+        let policy = RequestPolicy::default();
+        assert!(filter(&policy, "/sdapi/v1/img2img", request).is_err());
+    }
+
+    #[test_case("/sdapi/v1/txt2img/"; "trailing slash")]
+    #[test_case("//sdapi//v1/txt2img"; "repeated slashes")]
+    #[test_case("/sdapi/v1/txt2im%67"; "encoded")]
+    #[test_case("/sdapi/v1/img2img%2F?x=1"; "encoded trailing slash with query")]
+    fn rejected_path_forms_test(path: &str) {
+        let policy = RequestPolicy::default();
+        assert!(filter(&policy, path, json!({ "steps": 500 })).is_err());
+        assert!(filter(&policy, path, json!({ "script_name": "x/y/z plot" })).is_err());
+    }
+
+    #[test]
+    fn clamped_test() {
+        let policy = RequestPolicy {
+            mode: PolicyMode::Clamp,
+            ..Default::default()
+        };
+        let request = json!({
+            "prompt": "cat",
+            "width": 8192,
+            "height": 1024,
+            "steps": 500,
+            "enable_hr": true,
+            "hr_scale": 4,
+            "script_name": "x/y/z plot"
+        });
+
+        assert_eq!(
+            filter(&policy, "/sdapi/v1/txt2img", request),
+            Ok(json!({
+                "prompt": "cat",
+                "width": 2048,
+                "height": 1024,
+                "steps": 150,
+                "enable_hr": true,
+                "hr_scale": 1.0
+            }))
+        );
+    }
+
+    #[test]
+    fn other_endpoints_test() {
+        let policy = RequestPolicy::default();
+        let body = Some(b"not json".to_vec());
+        assert_eq!(
+            policy.filter("GET", "/sdapi/v1/txt2img", body.clone()),
+            Ok(body.clone())
+        );
+        assert_eq!(
+            policy.filter("POST", "/sdapi/v1/png-info", body.clone()),
+            Ok(body)
+        );
+    }
+}
@@ -1,27 +1,36 @@
-use std::fs;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::process::{ExitStatus, Stdio};
 use std::sync::Arc;
 
 use async_trait::async_trait;
 use serde::Deserialize;
-use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::process::{Child, Command};
 use tokio::sync::Mutex;
+use tokio_stream::StreamExt;
 
 use ya_agreement_utils::OfferTemplate;
 
-use crate::offer_template;
+use crate::benchmark::Workload;
+use crate::counters::EndpointClass;
+use crate::offer_template::{self, Capabilities};
+use crate::proxy::{AuditConfig, EndpointAccess, EndpointTimeout, QueueConfig};
+use crate::self_test::{self, TestReport};
 
-use super::{Runtime, RuntimeConfig};
-
-const OFFER_OVERRIDE_FILE_PATH_ENV: &str = "OFFER_OVERRIDE_FILE_PATH";
+use super::env::EnvConfig;
+use super::output_log::OutputLogConfig;
+use super::{
+    process_output, InferenceRequest, OutputStream, ProcessContext, Runtime, RuntimeConfig,
+    DEFAULT_MAX_LINE_LENGTH,
+};
 
 #[derive(Clone)]
 pub struct Dummy {
     child: Arc<Mutex<Child>>,
 }
 
+const DEFAULT_API_PORT: u16 = 7861;
+
 fn dummy_filename() -> String {
     format!("dummy{}", std::env::consts::EXE_SUFFIX)
 }
@@ -30,50 +39,116 @@ fn dummy_filename() -> String {
 pub(crate) struct Config {
     #[allow(dead_code)]
     pub dummy_arg: Option<String>,
+    /// Port of dummy runtime API. Defaults to 7861.
+    #[serde(default)]
+    pub api_port: Option<u16>,
+    #[serde(default)]
+    pub capabilities: Capabilities,
+    #[serde(default)]
+    pub endpoints: Vec<EndpointClass>,
+    #[serde(default)]
+    pub access: EndpointAccess,
+    #[serde(default)]
+    pub queue: QueueConfig,
+    #[serde(default)]
+    pub timeouts: Vec<EndpointTimeout>,
+    #[serde(default)]
+    pub audit: Option<AuditConfig>,
+    #[serde(default)]
+    pub metrics_address: Option<SocketAddr>,
+    #[serde(default)]
+    pub output_log: OutputLogConfig,
+    #[serde(default)]
+    pub env: EnvConfig,
+    /// Working directory of dummy process. Relative to activity work dir, which is the default.
+    #[serde(default)]
+    pub work_dir: Option<PathBuf>,
+}
+
+impl Config {
+    fn api_port(&self) -> u16 {
+        self.api_port.unwrap_or(DEFAULT_API_PORT)
+    }
 }
 
 impl RuntimeConfig for Config {
     fn gpu_uuid(&self) -> Option<String> {
         None
     }
+
+    fn api_url(&self) -> String {
+        format!("http://localhost:{}/", self.api_port())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities.clone()
+    }
+
+    fn endpoints(&self) -> Vec<EndpointClass> {
+        self.endpoints.clone()
+    }
+
+    fn access(&self) -> EndpointAccess {
+        self.access.clone()
+    }
+
+    fn queue(&self) -> QueueConfig {
+        self.queue.clone()
+    }
+
+    fn timeouts(&self) -> Vec<EndpointTimeout> {
+        self.timeouts.clone()
+    }
+
+    fn audit(&self) -> Option<AuditConfig> {
+        self.audit.clone()
+    }
+
+    fn metrics_address(&self) -> Option<SocketAddr> {
+        self.metrics_address
+    }
+
+    fn output_log(&self) -> OutputLogConfig {
+        self.output_log.clone()
+    }
 }
 
 #[async_trait]
 impl Runtime for Dummy {
     type CONFIG = Config;
 
-    async fn start(model: Option<PathBuf>, _config: Self::CONFIG) -> anyhow::Result<Dummy> {
+    async fn start(
+        model: Option<PathBuf>,
+        config: Self::CONFIG,
+        ctx: ProcessContext,
+    ) -> anyhow::Result<Dummy> {
         let dummy_filename = dummy_filename();
         let exe = super::find_file(dummy_filename)?;
         let mut cmd = Command::new(&exe);
-        let work_dir = exe.parent().unwrap();
+        let work_dir = ctx.runtime_dir(config.work_dir.as_deref())?;
+        cmd.args(["--port", &config.api_port().to_string()]);
         if let Some(model) = model {
             cmd.args(["--model", &model.to_string_lossy()]);
         }
+        config.env.apply(&mut cmd, &ctx);
         cmd.stdout(Stdio::piped())
+            .stderr(Stdio::piped())
             .stdin(Stdio::null())
-            .current_dir(work_dir);
+            .current_dir(&work_dir);
         let mut child = cmd.kill_on_drop(true).spawn()?;
 
-        let stdout = child.stdout.take();
-        if let Some(stdout) = stdout {
-            tokio::task::spawn_local(async move {
-                let mut stdout = BufReader::new(stdout);
-                loop {
-                    let mut line_buf = String::new();
-                    match stdout.read_line(&mut line_buf).await {
-                        Err(e) => {
-                            log::error!("no line: {}", e);
-                            break;
-                        }
-                        Ok(0) => break,
-                        Ok(_) => (),
-                    }
-                    let line = line_buf.trim_end();
-                    log::info!("dummy response: {line}");
+        let mut lines = process_output(&mut child, &ctx.output, DEFAULT_MAX_LINE_LENGTH)?;
+        tokio::task::spawn_local(async move {
+            while let Some(line) = lines.next().await {
+                match line {
+                    Ok(line) => match line.stream {
+                        OutputStream::Stdout => log::debug!("> {}", line.text),
+                        OutputStream::Stderr => log::debug!("!> {}", line.text),
+                    },
+                    Err(err) => log::error!("Failed to read line. Err {err}"),
                 }
-            });
-        }
+            }
+        });
 
         let child = Arc::new(Mutex::new(child));
         Ok(Self { child })
@@ -89,28 +164,32 @@ impl Runtime for Dummy {
         child.wait().await
     }
 
-    fn test(_config: &Self::CONFIG) -> anyhow::Result<()> {
-        Dummy::read_overrides().map(|_| ())
+    fn test(config: &Self::CONFIG, report: &mut TestReport) {
+        report.check("runtime-binary", || self_test::executable(dummy_filename()));
+        report.check_warning("api-port", || {
+            self_test::port_free("127.0.0.1", config.api_port())
+        });
     }
 
-    fn offer_template(config: &Self::CONFIG) -> anyhow::Result<OfferTemplate> {
-        let template = offer_template::template(config)?;
-        if let Ok(Some(overrides)) = Dummy::read_overrides() {
-            Ok(template.patch(overrides))
-        } else {
-            Ok(template)
+    fn inference_request(_config: &Self::CONFIG) -> InferenceRequest {
+        InferenceRequest {
+            path: "sdapi/v1/txt2img".into(),
+            body: serde_json::json!({ "width": 64, "height": 64 }),
         }
     }
-}
 
-impl Dummy {
-    fn read_overrides() -> anyhow::Result<Option<OfferTemplate>> {
-        if let Ok(override_json_path) = std::env::var(OFFER_OVERRIDE_FILE_PATH_ENV) {
-            let file = fs::File::open(override_json_path)?;
-            let overrides: OfferTemplate = serde_json::from_reader(file)?;
-            Ok(Some(overrides))
-        } else {
-            Ok(None)
+    fn benchmark_workload(_config: &Self::CONFIG) -> Workload {
+        Workload {
+            request: InferenceRequest {
+                path: "sdapi/v1/txt2img".into(),
+                body: serde_json::json!({ "width": 512, "height": 512 }),
+            },
+            metric: "requests-per-sec".into(),
+            results_per_request: 1,
         }
     }
+
+    fn offer_template(config: &Self::CONFIG) -> anyhow::Result<OfferTemplate> {
+        offer_template::template(config)
+    }
 }
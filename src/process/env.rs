@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+
+use serde::Deserialize;
+use tokio::process::Command;
+
+use super::ProcessContext;
+
+/// Environment of the runtime process.
+/// By default the process inherits the whole exe-unit environment.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub(crate) struct EnvConfig {
+    /// Clears inherited environment except `allow`ed variables.
+    pub clear: bool,
+
+    /// Inherited variables kept when environment is cleared.
+    pub allow: Vec<String>,
+
+    /// Removed inherited variables.
+    pub unset: Vec<String>,
+
+    /// Set variables. Values may contain `{work_dir}` and `{cache_dir}` of the activity.
+    pub set: BTreeMap<String, String>,
+}
+
+impl EnvConfig {
+    pub fn apply(&self, cmd: &mut Command, ctx: &ProcessContext) {
+        if self.clear {
+            cmd.env_clear();
+            for name in &self.allow {
+                if let Some(value) = std::env::var_os(name) {
+                    cmd.env(name, value);
+                }
+            }
+        }
+        for name in &self.unset {
+            cmd.env_remove(name);
+        }
+        for (name, value) in &self.set {
+            cmd.env(name, expand(value, ctx));
+        }
+    }
+}
+
+fn expand(value: &str, ctx: &ProcessContext) -> String {
+    value
+        .replace("{work_dir}", &ctx.work_dir.to_string_lossy())
+        .replace("{cache_dir}", &ctx.cache_dir.to_string_lossy())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::ffi::OsStr;
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn envs(cmd: &Command) -> HashMap<&OsStr, Option<&OsStr>> {
+        cmd.as_std().get_envs().collect()
+    }
+
+    #[test]
+    fn env_test() {
+        let ctx = ProcessContext {
+            work_dir: "/activity/work".into(),
+            cache_dir: "/activity/cache".into(),
+            ..Default::default()
+        };
+        let config: EnvConfig = serde_json::from_value(json!({
+            "unset": ["HF_TOKEN"],
+            "set": {
+                "CUDA_VISIBLE_DEVICES": "1",
+                "HF_HOME": "{cache_dir}/hf",
+                "TMP": "{work_dir}/tmp"
+            }
+        }))
+        .unwrap();
+        let mut cmd = Command::new("runtime");
+        config.apply(&mut cmd, &ctx);
+
+        let envs = envs(&cmd);
+        assert_eq!(envs[OsStr::new("HF_TOKEN")], None);
+        assert_eq!(
+            envs[OsStr::new("CUDA_VISIBLE_DEVICES")],
+            Some(OsStr::new("1"))
+        );
+        assert_eq!(
+            envs[OsStr::new("HF_HOME")],
+            Some(OsStr::new("/activity/cache/hf"))
+        );
+        assert_eq!(
+            envs[OsStr::new("TMP")],
+            Some(OsStr::new("/activity/work/tmp"))
+        );
+    }
+
+    #[test]
+    fn clear_env_test() {
+        let config = EnvConfig {
+            clear: true,
+            allow: vec!["PATH".into(), "YA_RUNTIME_AI_NOT_SET".into()],
+            ..Default::default()
+        };
+        let mut cmd = Command::new("runtime");
+        config.apply(&mut cmd, &ProcessContext::default());
+
+        let envs = envs(&cmd);
+        assert_eq!(envs.len(), usize::from(std::env::var_os("PATH").is_some()));
+        assert!(!envs.contains_key(OsStr::new("YA_RUNTIME_AI_NOT_SET")));
+    }
+}
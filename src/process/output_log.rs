@@ -0,0 +1,221 @@
+use std::fs;
+use std::io::{ErrorKind, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use anyhow::Context;
+use serde::Deserialize;
+
+use crate::process::OutputLine;
+use crate::rotating_file::RotatingFile;
+
+/// `Run` command entry point returning tail of backend output.
+/// Optional argument sets number of returned lines (per stream).
+pub const OUTPUT_ENTRY_POINT: &str = "output";
+
+pub const DEFAULT_OUTPUT_LINES: usize = 100;
+
+const TAIL_CHUNK_SIZE: u64 = 8 * 1024;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub(crate) struct OutputLogConfig {
+    /// Directory of backend output logs. Relative path is resolved against activity work dir.
+    pub dir: PathBuf,
+
+    /// Size of the file (in bytes) triggering its rotation.
+    pub max_file_size: u64,
+
+    /// Number of kept rotated files per stream.
+    pub max_files: usize,
+}
+
+impl Default for OutputLogConfig {
+    fn default() -> Self {
+        Self {
+            dir: "logs".into(),
+            max_file_size: 10 * 1024 * 1024,
+            max_files: 3,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+/// Backend stdout and stderr written to separate rotated files.
+/// Default instance discards the output.
+#[derive(Clone, Default)]
+pub(crate) struct OutputLog {
+    files: Option<Arc<OutputFiles>>,
+}
+
+struct OutputFiles {
+    stdout: Mutex<RotatingFile>,
+    stderr: Mutex<RotatingFile>,
+}
+
+impl OutputLog {
+    pub fn new(config: OutputLogConfig, work_dir: &Path) -> Self {
+        let dir = work_dir.join(&config.dir);
+        let file = |name: &str| {
+            Mutex::new(RotatingFile::new(
+                dir.join(name),
+                config.max_file_size,
+                config.max_files,
+            ))
+        };
+        let files = OutputFiles {
+            stdout: file("backend.stdout.log"),
+            stderr: file("backend.stderr.log"),
+        };
+        Self {
+            files: Some(Arc::new(files)),
+        }
+    }
+
+    fn file(&self, stream: OutputStream) -> Option<&Mutex<RotatingFile>> {
+        let files = self.files.as_ref()?;
+        Some(match stream {
+            OutputStream::Stdout => &files.stdout,
+            OutputStream::Stderr => &files.stderr,
+        })
+    }
+
+    /// Writes line prefixed with its timestamp.
+    pub fn write(&self, line: &OutputLine) {
+        let Some(file) = self.file(line.stream) else {
+            return;
+        };
+        let timestamp = line.timestamp.format("%Y-%m-%dT%H:%M:%S%.3fZ");
+        let mut file = file.lock().unwrap();
+        if let Err(err) = file.write(format!("{timestamp} {}\n", line.text).as_bytes()) {
+            log::error!(
+                "Failed to write backend output {:?}. Err {err}",
+                file.path()
+            );
+        }
+    }
+
+    /// Last `lines` lines of the stream (including the most recent rotated file).
+    pub fn tail(&self, stream: OutputStream, lines: usize) -> anyhow::Result<String> {
+        let Some(file) = self.file(stream) else {
+            return Ok(String::new());
+        };
+        let (path, rotated_path) = {
+            let file = file.lock().unwrap();
+            (file.path().to_path_buf(), file.rotated_path(1))
+        };
+        // Files are read without the lock, so reading them does not block backend output.
+        let mut tail = tail_lines(&path, lines)?;
+        if tail.len() < lines {
+            let mut rotated = tail_lines(&rotated_path, lines - tail.len())?;
+            rotated.append(&mut tail);
+            tail = rotated;
+        }
+        Ok(tail.into_iter().map(|line| format!("{line}\n")).collect())
+    }
+}
+
+/// Last `lines` lines of the file, read in chunks from its end.
+fn tail_lines(path: &Path, lines: usize) -> anyhow::Result<Vec<String>> {
+    let mut file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => anyhow::bail!("Failed to open {path:?}. Err {err}"),
+    };
+    let mut end = file.metadata()?.len();
+    let mut tail = Vec::new();
+    // Partial first line is read when there are more line breaks than requested lines.
+    while end > 0 && tail.iter().filter(|byte| **byte == b'\n').count() <= lines {
+        let start = end.saturating_sub(TAIL_CHUNK_SIZE);
+        let mut chunk = vec![0; (end - start) as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut chunk)
+            .with_context(|| format!("Failed to read {path:?}"))?;
+        chunk.append(&mut tail);
+        tail = chunk;
+        end = start;
+    }
+    let tail = String::from_utf8_lossy(&tail);
+    let tail = tail.lines().collect::<Vec<_>>();
+    let skip = tail.len().saturating_sub(lines);
+    Ok(tail[skip..].iter().map(|line| line.to_string()).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use super::*;
+
+    fn line(stream: OutputStream, text: &str) -> OutputLine {
+        OutputLine {
+            stream,
+            timestamp: Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap(),
+            text: text.into(),
+            redraw: false,
+        }
+    }
+
+    #[test]
+    fn output_log_test() {
+        let work_dir = std::env::temp_dir().join("ya-runtime-ai-output-log-test");
+        let _ = fs::remove_dir_all(&work_dir);
+        let config = OutputLogConfig {
+            max_file_size: 40,
+            max_files: 1,
+            ..Default::default()
+        };
+        let output = OutputLog::new(config, &work_dir);
+
+        for text in ["line 1", "line 2", "line 3", "line 4"] {
+            output.write(&line(OutputStream::Stdout, text));
+        }
+        output.write(&line(OutputStream::Stderr, "error"));
+
+        // Files hold single line and only one rotated file is kept.
+        assert_eq!(
+            output.tail(OutputStream::Stdout, 10).unwrap(),
+            "2024-05-01T12:00:00.000Z line 3\n2024-05-01T12:00:00.000Z line 4\n"
+        );
+        assert!(work_dir.join("logs/backend.stdout.log.1").exists());
+        assert_eq!(
+            output.tail(OutputStream::Stdout, 1).unwrap(),
+            "2024-05-01T12:00:00.000Z line 4\n"
+        );
+        assert_eq!(
+            output.tail(OutputStream::Stderr, 10).unwrap(),
+            "2024-05-01T12:00:00.000Z error\n"
+        );
+        assert_eq!(
+            OutputLog::default().tail(OutputStream::Stdout, 10).unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn tail_lines_test() {
+        let path = std::env::temp_dir().join("ya-runtime-ai-tail-lines-test.log");
+        let content = (0..5000)
+            .map(|index| format!("line {index}\n"))
+            .collect::<String>();
+        fs::write(&path, content).unwrap();
+
+        assert_eq!(
+            tail_lines(&path, 3).unwrap(),
+            vec!["line 4997", "line 4998", "line 4999"]
+        );
+        assert_eq!(tail_lines(&path, 2000).unwrap().len(), 2000);
+        assert_eq!(tail_lines(&path, 2000).unwrap()[0], "line 3000");
+        assert_eq!(tail_lines(&path, 10000).unwrap().len(), 5000);
+        assert!(tail_lines(&path, 0).unwrap().is_empty());
+
+        fs::remove_file(&path).unwrap();
+        assert!(tail_lines(&path, 3).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,493 @@
+//! GSB to HTTP proxy forwarding requestor calls to the runtime API.
+
+mod audit;
+mod queue;
+mod timeout;
+
+use std::path::Path;
+use std::pin::pin;
+use std::rc::Rc;
+use std::time::Duration;
+
+use futures::{stream, StreamExt, TryStreamExt};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
+use serde::Deserialize;
+use tokio::sync::OwnedSemaphorePermit;
+use tokio::time::Instant;
+
+use ya_gsb_http_proxy::error::HttpProxyStatusError;
+use ya_gsb_http_proxy::gsb_to_http::GsbToHttpProxy;
+use ya_gsb_http_proxy::message::{
+    GsbHttpCallMessage, GsbHttpCallResponseStreamChunk, GsbHttpCallStreamingMessage,
+};
+use ya_service_bus::typed as gsb;
+use ya_service_bus::RpcMessage;
+
+use crate::counters::{BusyGuard, RequestCounters};
+use crate::host_monitor::Suspension;
+use crate::metrics::Metrics;
+use crate::process::{Runtime, RuntimeConfig};
+
+use self::audit::{AuditEntry, AuditLog};
+use self::timeout::{Cancel, CancelGuard, Timeouts};
+
+pub(crate) use self::audit::AuditConfig;
+pub(crate) use self::queue::{QueueConfig, RequestQueue};
+pub(crate) use self::timeout::EndpointTimeout;
+
+/// Runtime API response passed back to the requestor.
+pub(crate) type ProxyResponse = <GsbHttpCallMessage as RpcMessage>::Item;
+
+/// Runtime API endpoint pattern.
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct Endpoint {
+    /// HTTP method. Matches any method when not set.
+    #[serde(default)]
+    pub method: Option<String>,
+    /// Regex matching whole request path (without leading `/` and query).
+    pub path: String,
+}
+
+impl Endpoint {
+    pub fn matcher(&self) -> anyhow::Result<EndpointMatcher> {
+        let path = Regex::new(&format!("^(?:{})$", self.path)).map_err(|err| {
+            anyhow::anyhow!("Invalid endpoint path pattern {}. Err {err}", self.path)
+        })?;
+        Ok(EndpointMatcher {
+            method: self.method.clone(),
+            path,
+        })
+    }
+}
+
+pub(crate) struct EndpointMatcher {
+    method: Option<String>,
+    path: Regex,
+}
+
+impl EndpointMatcher {
+    pub fn matches(&self, method: &str, path: &str) -> bool {
+        let method_matches = self.method.as_ref().map_or(true, |endpoint_method| {
+            endpoint_method.eq_ignore_ascii_case(method)
+        });
+        method_matches && self.path.is_match(request_path(path))
+    }
+}
+
+/// Characters not encoded in canonical path: unreserved, sub-delims, `:`, `@` and `/`.
+const PATH_CHARS: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'!')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'\'')
+    .remove(b'(')
+    .remove(b')')
+    .remove(b'*')
+    .remove(b'+')
+    .remove(b',')
+    .remove(b';')
+    .remove(b'=')
+    .remove(b':')
+    .remove(b'@');
+
+/// Request path the way runtime API routes it: percent-decoded, without empty segments
+/// and trailing `/`. Requests are checked, counted and passed to the runtime with canonical path,
+/// so encoded or redirected forms of a path are handled like the path itself.
+pub(crate) fn canonical_path(path: &str) -> Result<String, String> {
+    let (path, query) = match path.split_once('?') {
+        Some((path, query)) => (path, Some(query)),
+        None => (path, None),
+    };
+    let path = percent_decode_str(path)
+        .decode_utf8()
+        .map_err(|_| "Request path is not valid UTF-8".to_string())?;
+    let mut canonical = String::new();
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        // Dot segments could be resolved by HTTP client into denied path.
+        if segment == "." || segment == ".." {
+            return Err("Request path contains dot segments".into());
+        }
+        canonical.push('/');
+        canonical.extend(utf8_percent_encode(segment, PATH_CHARS));
+    }
+    if canonical.is_empty() {
+        canonical.push('/');
+    }
+    if let Some(query) = query {
+        canonical.push('?');
+        canonical.push_str(query);
+    }
+    Ok(canonical)
+}
+
+/// Request path without leading `/` and query.
+pub(crate) fn request_path(path: &str) -> &str {
+    let path = path.trim_start_matches('/');
+    path.split_once('?').map_or(path, |(path, _query)| path)
+}
+
+/// Runtime API endpoints available to requestors.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub(crate) struct EndpointAccess {
+    /// Allowed endpoints. All endpoints are allowed when empty.
+    pub allow: Vec<Endpoint>,
+    /// Denied endpoints. Deny takes precedence over allow.
+    pub deny: Vec<Endpoint>,
+}
+
+struct AccessPolicy {
+    allow: Vec<EndpointMatcher>,
+    deny: Vec<EndpointMatcher>,
+}
+
+impl AccessPolicy {
+    fn new(access: &EndpointAccess) -> anyhow::Result<Self> {
+        let matchers = |endpoints: &[Endpoint]| {
+            endpoints
+                .iter()
+                .map(Endpoint::matcher)
+                .collect::<anyhow::Result<Vec<_>>>()
+        };
+        Ok(Self {
+            allow: matchers(&access.allow)?,
+            deny: matchers(&access.deny)?,
+        })
+    }
+
+    /// `path` is canonical, so it has no dot segments (see [`canonical_path`]).
+    fn is_allowed(&self, method: &str, path: &str) -> bool {
+        let allowed = self.allow.is_empty()
+            || self
+                .allow
+                .iter()
+                .any(|endpoint| endpoint.matches(method, path));
+        allowed
+            && !self
+                .deny
+                .iter()
+                .any(|endpoint| endpoint.matches(method, path))
+    }
+}
+
+/// Runtime specific validation of requests passed to the runtime API.
+pub(crate) trait RequestFilter {
+    /// Returns (possibly modified) request body or a message explaining why request got rejected.
+    /// `path` is canonical (see [`canonical_path`]).
+    fn filter(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>, String>;
+}
+
+/// Wraps `GsbToHttpProxy` to check requests before passing them to the runtime.
+#[derive(Clone)]
+pub(crate) struct Proxy {
+    inner: GsbToHttpProxy,
+    suspension: Suspension,
+    request_counters: RequestCounters,
+    access: Rc<AccessPolicy>,
+    filter: Option<Rc<dyn RequestFilter>>,
+    queue: Rc<RequestQueue>,
+    timeouts: Rc<Timeouts>,
+    cancel: Option<Cancel>,
+    audit: Option<Rc<AuditLog>>,
+    metrics: Metrics,
+}
+
+impl Proxy {
+    pub fn new<RUNTIME: Runtime>(
+        inner: GsbToHttpProxy,
+        suspension: Suspension,
+        request_counters: RequestCounters,
+        queue: Rc<RequestQueue>,
+        config: &RUNTIME::CONFIG,
+        work_dir: &Path,
+        metrics: Metrics,
+    ) -> anyhow::Result<Self> {
+        let cancel = RUNTIME::cancel_path(config).map(|path| Cancel::new(&config.api_url(), &path));
+        Ok(Self {
+            inner,
+            suspension,
+            request_counters,
+            access: Rc::new(AccessPolicy::new(&config.access())?),
+            filter: RUNTIME::request_filter(config),
+            queue,
+            timeouts: Rc::new(Timeouts::new(&config.timeouts())?),
+            cancel,
+            audit: config
+                .audit()
+                .map(|audit| Rc::new(AuditLog::new(audit, work_dir))),
+            metrics,
+        })
+    }
+
+    pub fn bind(&self, gsb_path: &str) {
+        let this = self.clone();
+        gsb::bind(gsb_path, move |message: GsbHttpCallMessage| {
+            let mut this = this.clone();
+            async move { this.call(message).await }
+        });
+    }
+
+    /// Checks the request and passes it to the runtime.
+    pub async fn call(
+        &mut self,
+        mut message: GsbHttpCallMessage,
+    ) -> Result<ProxyResponse, HttpProxyStatusError> {
+        message.path = canonical(&message.path)?;
+        self.check(&message.method, &message.path)?;
+        message.body = self.filter(&message.method, &message.path, message.body.take())?;
+        let permit = self.acquire(&message.method, &message.path).await?;
+        let (mut audit, _processing) =
+            self.start_request(&message.method, &message.path, &message.body);
+        let (path, started) = (message.path.clone(), Instant::now());
+
+        let timeout = self.timeouts.timeout(&message.method, &message.path);
+        let guard = CancelGuard::new(self.cancel.clone(), permit);
+        let response = self.inner.pass(message);
+        let response = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, response).await {
+                Ok(response) => {
+                    guard.complete();
+                    response
+                }
+                // Guard dropped without completion cancels the request.
+                Err(_) => Err(timeout_error(timeout)),
+            },
+            None => {
+                let response = response.await;
+                guard.complete();
+                response
+            }
+        };
+        if let Some(audit) = &mut audit {
+            match &response {
+                Ok(response) => {
+                    audit.status(response.header.status_code);
+                    audit.response_body(&response.body.msg_bytes);
+                }
+                Err(err) => audit.error(err),
+            }
+        }
+        self.metrics.observe_request(&path, started.elapsed());
+        response
+    }
+
+    pub fn bind_streaming(&self, gsb_path: &str) {
+        let this = self.clone();
+        gsb::bind_stream(gsb_path, move |mut message: GsbHttpCallStreamingMessage| {
+            let mut this = this.clone();
+            stream::once(async move {
+                message.path = canonical(&message.path)?;
+                this.check(&message.method, &message.path)?;
+                message.body = this.filter(&message.method, &message.path, message.body.take())?;
+                let permit = this.acquire(&message.method, &message.path).await?;
+                let (mut audit, processing) =
+                    this.start_request(&message.method, &message.path, &message.body);
+                let (path, started) = (message.path.clone(), Instant::now());
+
+                let deadline = this
+                    .timeouts
+                    .timeout(&message.method, &message.path)
+                    .map(|timeout| (Instant::now() + timeout, timeout));
+                let guard = CancelGuard::new(this.cancel.clone(), permit);
+                let response = this.inner.pass_streaming(message);
+                let metrics = this.metrics.clone();
+                // Guard (holding queue permit) completed and audit entry written when response ends.
+                let response = async_stream::stream! {
+                    let mut response = pin!(response);
+                    loop {
+                        let chunk = match deadline {
+                            Some((deadline, timeout)) => {
+                                match tokio::time::timeout_at(deadline, response.next()).await {
+                                    Ok(chunk) => chunk,
+                                    Err(_) => {
+                                        let err = timeout_error(timeout);
+                                        if let Some(audit) = &mut audit {
+                                            audit.error(&err);
+                                        }
+                                        metrics.observe_request(&path, started.elapsed());
+                                        // Guard dropped without completion cancels the request.
+                                        yield Err(err);
+                                        return;
+                                    }
+                                }
+                            }
+                            None => response.next().await,
+                        };
+                        let Some(chunk) = chunk else {
+                            break;
+                        };
+                        if let Some(audit) = &mut audit {
+                            match &chunk {
+                                Ok(GsbHttpCallResponseStreamChunk::Header(header)) => {
+                                    audit.status(header.status_code)
+                                }
+                                Ok(GsbHttpCallResponseStreamChunk::Body(body)) => {
+                                    audit.response_body(&body.msg_bytes)
+                                }
+                                Err(err) => audit.error(err),
+                            }
+                        }
+                        yield chunk;
+                    }
+                    guard.complete();
+                    drop(processing);
+                    metrics.observe_request(&path, started.elapsed());
+                };
+                Ok::<_, HttpProxyStatusError>(response)
+            })
+            .try_flatten()
+            .boxed_local()
+        });
+    }
+
+    /// Counts request passed to the runtime and starts its audit log entry.
+    /// Processing time of counted requests is billed until returned guard is dropped.
+    fn start_request(
+        &self,
+        method: &str,
+        path: &str,
+        body: &Option<Vec<u8>>,
+    ) -> (Option<AuditEntry>, Option<BusyGuard>) {
+        let counted = self.request_counters.count(method, path);
+        let processing = counted.as_ref().map(|_| self.request_counters.processing());
+        let audit = self
+            .audit
+            .as_ref()
+            .map(|audit| audit.entry(method, path, body.as_deref(), counted));
+        (audit, processing)
+    }
+
+    fn check(&self, method: &str, path: &str) -> Result<(), HttpProxyStatusError> {
+        if !self.access.is_allowed(method, path) {
+            log::warn!("Forbidden request {method} {path}");
+            return Err(HttpProxyStatusError::RuntimeException(format!(
+                "403 Forbidden. {method} {path} is not allowed by the provider"
+            )));
+        }
+        if self.suspension.is_suspended() {
+            return Err(HttpProxyStatusError::RuntimeException(
+                "Provider's GPU is temporarily used by its owner. Try again later".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn acquire(
+        &self,
+        method: &str,
+        path: &str,
+    ) -> Result<Option<OwnedSemaphorePermit>, HttpProxyStatusError> {
+        self.queue.acquire(method, path).await.map_err(|message| {
+            HttpProxyStatusError::RuntimeException(format!("503 Service Unavailable. {message}"))
+        })
+    }
+
+    fn filter(
+        &self,
+        method: &str,
+        path: &str,
+        body: Option<Vec<u8>>,
+    ) -> Result<Option<Vec<u8>>, HttpProxyStatusError> {
+        let Some(filter) = &self.filter else {
+            return Ok(body);
+        };
+        filter.filter(method, path, body).map_err(|message| {
+            log::warn!("Rejected request {method} {path}. {message}");
+            HttpProxyStatusError::RuntimeException(format!("400 Bad Request. {message}"))
+        })
+    }
+}
+
+fn canonical(path: &str) -> Result<String, HttpProxyStatusError> {
+    canonical_path(path).map_err(|message| {
+        log::warn!("Rejected request path {path}. {message}");
+        HttpProxyStatusError::RuntimeException(format!("400 Bad Request. {message}"))
+    })
+}
+
+fn timeout_error(timeout: Duration) -> HttpProxyStatusError {
+    log::warn!("Request timed out after {timeout:?}");
+    HttpProxyStatusError::RuntimeException(format!(
+        "504 Gateway Timeout. Request not handled in {timeout:?}"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use test_case::test_case;
+
+    use super::*;
+
+    fn policy() -> AccessPolicy {
+        let access: EndpointAccess = serde_json::from_value(json!({
+            "allow": [
+                { "path": "sdapi/v1/.*" },
+                { "method": "GET", "path": "internal/ping" }
+            ],
+            "deny": [
+                { "path": "sdapi/v1/server-(kill|restart|stop)" },
+                { "method": "POST", "path": "sdapi/v1/options" }
+            ]
+        }))
+        .unwrap();
+        AccessPolicy::new(&access).unwrap()
+    }
+
+    #[test_case("POST", "/sdapi/v1/txt2img", true; "allowed")]
+    #[test_case("GET", "/sdapi/v1/options", true; "allowed method")]
+    #[test_case("GET", "/internal/ping?x=1", true; "allowed with query")]
+    #[test_case("POST", "/sdapi/v1/options", false; "denied method")]
+    #[test_case("POST", "/sdapi/v1/server-kill", false; "denied")]
+    #[test_case("POST", "/sdapi/v1/server-kill?now=1", false; "denied with query")]
+    #[test_case("POST", "/internal/ping", false; "not allowed method")]
+    #[test_case("POST", "/extensions/install", false; "not allowed")]
+    #[test_case("POST", "/sdapi/v1/x/../server-kill", false; "dot segments")]
+    #[test_case("POST", "/sdapi/v1/x/%2E%2E/server-kill", false; "encoded dot segments")]
+    #[test_case("POST", "/sdapi/v1/server-%6Bill", false; "encoded denied")]
+    #[test_case("POST", "/sdapi/v1/server-kill/", false; "denied with trailing slash")]
+    #[test_case("POST", "//sdapi//v1//server-kill", false; "denied with repeated slashes")]
+    #[test_case("POST", "/sdapi/v1/txt2im%67/", true; "encoded allowed")]
+    #[test_case("GET", "/sdapi/v1/options?name=a..b", true; "dots in query")]
+    #[test_case("GET", "/sdapi/v1/options?file=x%2Epng", true; "encoded dot in query")]
+    #[test_case("GET", "/sdapi/v1/file=x%2Epng", true; "encoded dot in segment")]
+    fn access_policy_test(method: &str, path: &str, allowed: bool) {
+        let allowed_path = canonical_path(path).map_or(false, |path| {
+            // Canonicalization is done once by the proxy.
+            assert_eq!(canonical_path(&path).unwrap(), path);
+            policy().is_allowed(method, &path)
+        });
+        assert_eq!(allowed_path, allowed);
+    }
+
+    #[test_case("/sdapi/v1/txt2img", Ok("/sdapi/v1/txt2img"); "canonical")]
+    #[test_case("/sdapi/v1/txt2img/", Ok("/sdapi/v1/txt2img"); "trailing slash")]
+    #[test_case("sdapi//v1///txt2img", Ok("/sdapi/v1/txt2img"); "repeated slashes")]
+    #[test_case("/sdapi/v1/txt2im%67", Ok("/sdapi/v1/txt2img"); "encoded")]
+    #[test_case("/sdapi%2Fv1%2ftxt2img", Ok("/sdapi/v1/txt2img"); "encoded slash")]
+    #[test_case("/files/a%20b%3Fc%25", Ok("/files/a%20b%3Fc%25"); "encoded reserved")]
+    #[test_case("/sdapi/v1/options/?x=%2F/", Ok("/sdapi/v1/options?x=%2F/"); "query")]
+    #[test_case("/", Ok("/"); "root")]
+    #[test_case("/sdapi/./v1", Err(()); "dot segment")]
+    #[test_case("/sdapi/v1/%2e%2E/x", Err(()); "encoded dot segments")]
+    #[test_case("/sdapi/%FF", Err(()); "invalid utf8")]
+    fn canonical_path_test(path: &str, expected: Result<&str, ()>) {
+        assert_eq!(canonical_path(path).as_deref().map_err(|_| ()), expected);
+    }
+
+    #[test]
+    fn empty_access_policy_test() {
+        let policy = AccessPolicy::new(&EndpointAccess::default()).unwrap();
+        assert!(policy.is_allowed("POST", "/sdapi/v1/server-kill"));
+    }
+}
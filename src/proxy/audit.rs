@@ -0,0 +1,221 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Instant;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::rotating_file::RotatingFile;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub(crate) struct AuditConfig {
+    /// Audit log file. Relative path is resolved against activity work dir.
+    pub file: PathBuf,
+
+    /// Size of the file (in bytes) triggering its rotation.
+    pub max_file_size: u64,
+
+    /// Number of kept rotated files (`<file>.1`, `<file>.2`, ...).
+    pub max_files: usize,
+
+    /// Logs SHA-256 of request and response bodies.
+    pub hash_bodies: bool,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            file: "audit.jsonl".into(),
+            max_file_size: 10 * 1024 * 1024,
+            max_files: 5,
+            hash_bodies: false,
+        }
+    }
+}
+
+/// JSON lines log of proxied requests.
+pub(crate) struct AuditLog {
+    hash_bodies: bool,
+    file: RefCell<RotatingFile>,
+}
+
+#[derive(Serialize, Debug)]
+struct AuditRecord {
+    timestamp: DateTime<Utc>,
+    method: String,
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    latency_ms: u64,
+    request_size: usize,
+    response_size: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    request_sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_sha256: Option<String>,
+    /// Counter increases caused by the request.
+    counters: BTreeMap<String, f64>,
+}
+
+impl AuditLog {
+    pub fn new(config: AuditConfig, work_dir: &Path) -> Self {
+        let path = work_dir.join(&config.file);
+        Self {
+            hash_bodies: config.hash_bodies,
+            file: RefCell::new(RotatingFile::new(
+                path,
+                config.max_file_size,
+                config.max_files,
+            )),
+        }
+    }
+
+    /// Starts audit log entry written when dropped.
+    pub fn entry(
+        self: &Rc<Self>,
+        method: &str,
+        path: &str,
+        body: Option<&[u8]>,
+        counters: impl IntoIterator<Item = (String, f64)>,
+    ) -> AuditEntry {
+        let body = body.unwrap_or_default();
+        let hash_bodies = self.hash_bodies;
+        AuditEntry {
+            log: self.clone(),
+            started: Instant::now(),
+            response_hasher: hash_bodies.then(Sha256::new),
+            record: AuditRecord {
+                timestamp: Utc::now(),
+                method: method.to_string(),
+                path: path.to_string(),
+                status: None,
+                error: None,
+                latency_ms: 0,
+                request_size: body.len(),
+                response_size: 0,
+                request_sha256: hash_bodies.then(|| hex::encode(Sha256::digest(body))),
+                response_sha256: None,
+                counters: counters.into_iter().collect(),
+            },
+        }
+    }
+
+    fn write(&self, record: &AuditRecord) {
+        if let Err(err) = self.try_write(record) {
+            log::error!(
+                "Failed to write audit log {:?}. Err {err}",
+                self.file.borrow().path()
+            );
+        }
+    }
+
+    fn try_write(&self, record: &AuditRecord) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.borrow_mut().write(&line)
+    }
+}
+
+/// Audit log entry of single request, written when dropped
+/// (also when requestor stops waiting for the response).
+pub(crate) struct AuditEntry {
+    log: Rc<AuditLog>,
+    started: Instant,
+    response_hasher: Option<Sha256>,
+    record: AuditRecord,
+}
+
+impl AuditEntry {
+    pub fn status(&mut self, status: u16) {
+        self.record.status = Some(status);
+    }
+
+    pub fn response_body(&mut self, body: &[u8]) {
+        self.record.response_size += body.len();
+        if let Some(hasher) = &mut self.response_hasher {
+            hasher.update(body);
+        }
+    }
+
+    pub fn error(&mut self, error: impl Display) {
+        self.record.error = Some(error.to_string());
+    }
+}
+
+impl Drop for AuditEntry {
+    fn drop(&mut self) {
+        self.record.latency_ms = self.started.elapsed().as_millis() as u64;
+        if let Some(hasher) = self.response_hasher.take() {
+            self.record.response_sha256 = Some(hex::encode(hasher.finalize()));
+        }
+        if self.record.status.is_none() && self.record.error.is_none() {
+            self.record.error = Some("Request cancelled".into());
+        }
+        self.log.write(&self.record);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use serde_json::Value;
+
+    use super::*;
+
+    fn lines(path: &Path) -> Vec<Value> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn audit_log_test() {
+        let work_dir = std::env::temp_dir().join("ya-runtime-ai-audit-test");
+        let _ = fs::remove_dir_all(&work_dir);
+        let config = AuditConfig {
+            max_file_size: 600,
+            max_files: 1,
+            hash_bodies: true,
+            ..Default::default()
+        };
+        let audit = Rc::new(AuditLog::new(config, &work_dir));
+
+        for _ in 0..3 {
+            let counters = [("ai-runtime.requests".to_string(), 1.0)];
+            let mut entry = audit.entry("POST", "/sdapi/v1/txt2img", Some(&b"{}"[..]), counters);
+            entry.status(200);
+            entry.response_body(b"image");
+        }
+        drop(audit.entry("GET", "/sdapi/v1/options", None, []));
+
+        // Every record exceeds half of `max_file_size`, so each write rotates the file.
+        let rotated = lines(&work_dir.join("audit.jsonl.1"));
+        let current = lines(&work_dir.join("audit.jsonl"));
+        assert_eq!(rotated.len(), 1);
+        assert_eq!(current.len(), 1);
+        assert!(!work_dir.join("audit.jsonl.2").exists());
+
+        let last = current.last().unwrap();
+        assert_eq!(last["path"], "/sdapi/v1/options");
+        assert_eq!(last["error"], "Request cancelled");
+        let first = &rotated[0];
+        assert_eq!(first["status"], 200);
+        assert_eq!(first["request_size"], 2);
+        assert_eq!(first["response_size"], 5);
+        assert_eq!(first["counters"]["ai-runtime.requests"], 1.0);
+        assert_eq!(
+            first["response_sha256"],
+            "6105d6cc76af400325e94d588ce511be5bfdbb73b437dc51eca43917d7a43e3d"
+        );
+    }
+}
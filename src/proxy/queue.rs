@@ -0,0 +1,240 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::{Endpoint, EndpointMatcher};
+use crate::counters::RequestCounter;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub(crate) struct QueueConfig {
+    /// Maximum number of requests passed to the runtime at once. `None` disables the limit.
+    pub max_in_flight: Option<usize>,
+
+    /// Maximum number of requests waiting for the runtime. Requests above the limit are rejected.
+    pub max_queued: usize,
+
+    #[serde(with = "humantime_serde")]
+    pub queue_timeout: Duration,
+
+    /// Endpoints of requests passed through the queue. Other requests (e.g. progress polling
+    /// or interrupting) go to the runtime directly. Empty list queues requests to all endpoints.
+    pub endpoints: Vec<Endpoint>,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self {
+            max_in_flight: None,
+            max_queued: 16,
+            queue_timeout: Duration::from_secs(300),
+            endpoints: Vec::new(),
+        }
+    }
+}
+
+/// FIFO queue of requests waiting for the runtime.
+pub(crate) struct RequestQueue {
+    config: QueueConfig,
+    permits: Option<Arc<Semaphore>>,
+    endpoints: Vec<EndpointMatcher>,
+    queued: Arc<AtomicUsize>,
+    /// Total time (in seconds) requests spent in the queue.
+    wait_sec: Arc<Mutex<f64>>,
+}
+
+impl RequestQueue {
+    pub fn new(config: QueueConfig) -> anyhow::Result<Self> {
+        let permits = config
+            .max_in_flight
+            .map(|max_in_flight| Arc::new(Semaphore::new(max_in_flight)));
+        let endpoints = config
+            .endpoints
+            .iter()
+            .map(Endpoint::matcher)
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            config,
+            permits,
+            endpoints,
+            queued: Default::default(),
+            wait_sec: Default::default(),
+        })
+    }
+
+    /// Counter of total time requests spent in the queue.
+    pub fn wait_counter(&self) -> RequestCounter {
+        RequestCounter::new(self.wait_sec.clone())
+    }
+
+    /// Waits for the runtime to accept next request.
+    /// Returned permit has to be held until request is handled.
+    pub async fn acquire(
+        &self,
+        method: &str,
+        path: &str,
+    ) -> Result<Option<OwnedSemaphorePermit>, String> {
+        let Some(permits) = &self.permits else {
+            return Ok(None);
+        };
+        let queued_endpoint = self.endpoints.is_empty()
+            || self
+                .endpoints
+                .iter()
+                .any(|endpoint| endpoint.matches(method, path));
+        if !queued_endpoint {
+            return Ok(None);
+        }
+        if let Ok(permit) = permits.clone().try_acquire_owned() {
+            return Ok(Some(permit));
+        }
+
+        let queued = self.queued.fetch_add(1, Ordering::SeqCst);
+        if queued >= self.config.max_queued {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            log::warn!("Request rejected. Queue is full ({queued} requests)");
+            return Err(format!("Request queue is full ({queued} requests)"));
+        }
+        let queued = Queued {
+            queue: self,
+            started: Instant::now(),
+        };
+        log::info!("Request queued. Queue depth {}", self.depth());
+
+        let permit =
+            tokio::time::timeout(self.config.queue_timeout, permits.clone().acquire_owned()).await;
+        let wait = queued.started.elapsed();
+        drop(queued);
+        match permit {
+            Ok(Ok(permit)) => {
+                log::info!("Request waited {:.3}s in queue", wait.as_secs_f64());
+                Ok(Some(permit))
+            }
+            Ok(Err(err)) => Err(format!("Request queue closed. Err {err}")),
+            Err(_) => {
+                log::warn!("Request rejected after waiting {wait:?} in queue");
+                Err(format!(
+                    "Request waited in queue longer than {:?}",
+                    self.config.queue_timeout
+                ))
+            }
+        }
+    }
+
+    /// Number of requests waiting in the queue.
+    pub fn depth(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /// Shared number of requests waiting in the queue.
+    pub fn depth_gauge(&self) -> Arc<AtomicUsize> {
+        self.queued.clone()
+    }
+}
+
+/// Request waiting in the queue. Leaves the queue when dropped, also when requestor stops waiting.
+struct Queued<'a> {
+    queue: &'a RequestQueue,
+    started: Instant,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.queue.queued.fetch_sub(1, Ordering::SeqCst);
+        *self.queue.wait_sec.lock().unwrap() += self.started.elapsed().as_secs_f64();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queue(max_queued: usize) -> RequestQueue {
+        RequestQueue::new(QueueConfig {
+            max_in_flight: Some(1),
+            max_queued,
+            queue_timeout: Duration::from_millis(100),
+            endpoints: Vec::new(),
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn unlimited_queue_test() {
+        let queue = RequestQueue::new(QueueConfig::default()).unwrap();
+        assert!(queue.acquire("POST", "/txt2img").await.unwrap().is_none());
+        assert!(queue.acquire("POST", "/txt2img").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn queue_timeout_test() {
+        let queue = queue(1);
+        let _permit = queue.acquire("POST", "/txt2img").await.unwrap();
+
+        assert!(queue.acquire("POST", "/txt2img").await.is_err());
+        assert_eq!(queue.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn queue_full_test() {
+        let queue = queue(0);
+        let _permit = queue.acquire("POST", "/txt2img").await.unwrap();
+
+        assert!(queue.acquire("POST", "/txt2img").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn queued_request_test() {
+        let queue = Arc::new(queue(1));
+        let permit = queue.acquire("POST", "/txt2img").await.unwrap();
+
+        let waiting = tokio::spawn({
+            let queue = queue.clone();
+            async move {
+                queue
+                    .acquire("POST", "/txt2img")
+                    .await
+                    .map(|permit| permit.is_some())
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(queue.depth(), 1);
+
+        drop(permit);
+        assert_eq!(waiting.await.unwrap(), Ok(true));
+    }
+
+    #[tokio::test]
+    async fn queued_endpoints_test() {
+        let queue = RequestQueue::new(QueueConfig {
+            max_in_flight: Some(1),
+            endpoints: vec![Endpoint {
+                method: Some("POST".into()),
+                path: "txt2img".into(),
+            }],
+            ..Default::default()
+        })
+        .unwrap();
+        let _permit = queue.acquire("POST", "/txt2img").await.unwrap();
+
+        let progress = queue.acquire("GET", "/progress?skip_current_image=true");
+        assert!(matches!(progress.await, Ok(None)));
+        assert_eq!(queue.depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn dropped_request_test() {
+        let queue = queue(1);
+        let _permit = queue.acquire("POST", "/txt2img").await.unwrap();
+
+        let waiting =
+            tokio::time::timeout(Duration::from_millis(10), queue.acquire("POST", "/txt2img"))
+                .await;
+        assert!(waiting.is_err());
+        assert_eq!(queue.depth(), 0);
+        assert!(*queue.wait_sec.lock().unwrap() > 0.0);
+    }
+}
@@ -0,0 +1,154 @@
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::sync::OwnedSemaphorePermit;
+
+use super::{Endpoint, EndpointMatcher};
+
+const CANCEL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Time limit of requests to matching endpoint.
+#[derive(Deserialize, Clone, Debug)]
+pub(crate) struct EndpointTimeout {
+    #[serde(flatten)]
+    pub endpoint: Endpoint,
+    #[serde(with = "humantime_serde")]
+    pub timeout: Duration,
+}
+
+/// Timeouts of the first matching endpoint. Requests to other endpoints have no time limit.
+pub(crate) struct Timeouts {
+    timeouts: Vec<(EndpointMatcher, Duration)>,
+}
+
+impl Timeouts {
+    pub fn new(timeouts: &[EndpointTimeout]) -> anyhow::Result<Self> {
+        let timeouts = timeouts
+            .iter()
+            .map(|timeout| Ok((timeout.endpoint.matcher()?, timeout.timeout)))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { timeouts })
+    }
+
+    pub fn timeout(&self, method: &str, path: &str) -> Option<Duration> {
+        self.timeouts
+            .iter()
+            .find(|(endpoint, _)| endpoint.matches(method, path))
+            .map(|(_, timeout)| *timeout)
+    }
+}
+
+/// Runtime API call interrupting request processing.
+#[derive(Clone)]
+pub(crate) struct Cancel {
+    client: reqwest::Client,
+    url: String,
+}
+
+impl Cancel {
+    pub fn new(api_url: &str, path: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: format!("{api_url}{path}"),
+        }
+    }
+
+    async fn send(self) {
+        log::info!("Cancelling request processing: POST {}", self.url);
+        let result = self
+            .client
+            .post(&self.url)
+            .timeout(CANCEL_TIMEOUT)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+        if let Err(err) = result {
+            log::error!("Failed to cancel request processing. Err {err}");
+        }
+    }
+}
+
+/// Cancels request processing when dropped before the request completes,
+/// i.e. on timeout or when requestor stops waiting for the response.
+/// Holds request queue permit, so the next request is not passed to the runtime
+/// before the cancel call completes (and cancels the next request instead).
+pub(crate) struct CancelGuard {
+    cancel: Option<Cancel>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl CancelGuard {
+    pub fn new(cancel: Option<Cancel>, permit: Option<OwnedSemaphorePermit>) -> Self {
+        Self { cancel, permit }
+    }
+
+    /// Marks request as completed.
+    pub fn complete(mut self) {
+        self.cancel = None;
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if let Some(cancel) = self.cancel.take() {
+            let permit = self.permit.take();
+            tokio::task::spawn_local(async move {
+                cancel.send().await;
+                drop(permit);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::json;
+    use tokio::sync::Semaphore;
+
+    use super::*;
+
+    #[test]
+    fn endpoint_timeouts_test() {
+        let timeouts: Vec<EndpointTimeout> = serde_json::from_value(json!([
+            { "method": "POST", "path": "sdapi/v1/txt2img", "timeout": "10m" },
+            { "path": ".*", "timeout": "30s" }
+        ]))
+        .unwrap();
+        let timeouts = Timeouts::new(&timeouts).unwrap();
+
+        assert_eq!(
+            timeouts.timeout("POST", "/sdapi/v1/txt2img"),
+            Some(Duration::from_secs(600))
+        );
+        assert_eq!(
+            timeouts.timeout("GET", "/sdapi/v1/txt2img"),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(Timeouts::new(&[]).unwrap().timeout("GET", "/"), None);
+    }
+
+    #[tokio::test]
+    async fn cancel_guard_permit_test() {
+        let local = tokio::task::LocalSet::new();
+        local
+            .run_until(async {
+                let semaphore = Arc::new(Semaphore::new(1));
+
+                let permit = semaphore.clone().acquire_owned().await.unwrap();
+                CancelGuard::new(None, Some(permit)).complete();
+                assert_eq!(semaphore.available_permits(), 1);
+
+                // Nothing listens on port 1, so cancel call fails fast.
+                let cancel = Cancel::new("http://127.0.0.1:1/", "sdapi/v1/interrupt");
+                let permit = semaphore.clone().acquire_owned().await.unwrap();
+                drop(CancelGuard::new(Some(cancel), Some(permit)));
+                assert_eq!(semaphore.available_permits(), 0);
+
+                let permit = tokio::time::timeout(2 * CANCEL_TIMEOUT, semaphore.acquire()).await;
+                assert!(permit.is_ok());
+            })
+            .await;
+    }
+}
@@ -0,0 +1,75 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Append-only file renamed to `<file>.1`, `<file>.2`, ... when it exceeds `max_file_size`.
+pub(crate) struct RotatingFile {
+    path: PathBuf,
+    max_file_size: u64,
+    max_files: usize,
+    /// Open file and its size.
+    file: Option<(File, u64)>,
+}
+
+impl RotatingFile {
+    pub fn new(path: PathBuf, max_file_size: u64, max_files: usize) -> Self {
+        Self {
+            path,
+            max_file_size,
+            max_files,
+            file: None,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Path of `index`-th rotated file (`1` is the most recent one).
+    pub fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        PathBuf::from(path)
+    }
+
+    /// Writes `data` rotating the file first if `data` does not fit in it.
+    pub fn write(&mut self, data: &[u8]) -> anyhow::Result<()> {
+        if let Some((_, size)) = self.file.as_ref() {
+            if *size > 0 && size + data.len() as u64 > self.max_file_size {
+                self.file = None;
+                self.rotate()?;
+            }
+        }
+        if self.file.is_none() {
+            if let Some(dir) = self.path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            let size = file.metadata()?.len();
+            self.file = Some((file, size));
+        }
+        if let Some((file, size)) = self.file.as_mut() {
+            file.write_all(data)?;
+            *size += data.len() as u64;
+        }
+        Ok(())
+    }
+
+    fn rotate(&self) -> anyhow::Result<()> {
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+            return Ok(());
+        }
+        for index in (1..self.max_files).rev() {
+            let from = self.rotated_path(index);
+            if from.exists() {
+                fs::rename(from, self.rotated_path(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated_path(1))?;
+        Ok(())
+    }
+}
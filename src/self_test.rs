@@ -0,0 +1,322 @@
+//! Runtime tests run by `test` command.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::io::{self, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use ya_gsb_http_proxy::gsb_to_http::GsbToHttpProxy;
+use ya_gsb_http_proxy::message::GsbHttpCallMessage;
+
+use crate::counters::RequestCounters;
+use crate::host_monitor::Suspension;
+use crate::metrics::Metrics;
+use crate::offer_template::gpu_detection;
+use crate::process::{find_file, InferenceRequest, ProcessContext, Runtime, RuntimeConfig};
+use crate::proxy::{Proxy, RequestQueue};
+
+const API_READY_TIMEOUT: Duration = Duration::from_secs(60);
+const API_READY_POLL_DELAY: Duration = Duration::from_millis(500);
+const INFERENCE_TIMEOUT: Duration = Duration::from_secs(300);
+const STOP_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default)]
+pub enum ReportFormat {
+    /// One line per check
+    #[default]
+    Human,
+    /// JSON document
+    Json,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum CheckStatus {
+    Passed,
+    Failed,
+    /// Failed check not failing the tests.
+    Warning,
+    Skipped,
+}
+
+impl Display for CheckStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Passed => f.pad("PASSED"),
+            Self::Failed => f.pad("FAILED"),
+            Self::Warning => f.pad("WARNING"),
+            Self::Skipped => f.pad("SKIPPED"),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) struct TestReport {
+    pub runtime: String,
+    pub passed: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+impl TestReport {
+    pub fn new(runtime: &str) -> Self {
+        Self {
+            runtime: runtime.to_string(),
+            passed: true,
+            checks: Vec::new(),
+        }
+    }
+
+    /// Runs `check` returning a message describing passed check.
+    pub fn check(&mut self, name: &str, check: impl FnOnce() -> anyhow::Result<String>) {
+        self.record(name, check())
+    }
+
+    /// Runs `check` which failure is only a warning,
+    /// e.g. checking a resource which may be used by a running activity.
+    pub fn check_warning(&mut self, name: &str, check: impl FnOnce() -> anyhow::Result<String>) {
+        match check() {
+            Ok(message) => self.push(name, CheckStatus::Passed, message),
+            Err(err) => self.push(name, CheckStatus::Warning, format!("{err:#}")),
+        }
+    }
+
+    pub fn record(&mut self, name: &str, result: anyhow::Result<String>) {
+        match result {
+            Ok(message) => self.push(name, CheckStatus::Passed, message),
+            Err(err) => self.push(name, CheckStatus::Failed, format!("{err:#}")),
+        }
+    }
+
+    fn push(&mut self, name: &str, status: CheckStatus, message: String) {
+        log::info!("Test {name}: {status}. {message}");
+        self.passed &= status != CheckStatus::Failed;
+        self.checks.push(CheckResult {
+            name: name.to_string(),
+            status,
+            message,
+        });
+    }
+
+    pub fn skip(&mut self, name: &str, reason: &str) {
+        log::info!("Test {name}: skipped. {reason}");
+        self.checks.push(CheckResult {
+            name: name.to_string(),
+            status: CheckStatus::Skipped,
+            message: reason.to_string(),
+        });
+    }
+
+    pub fn print(&self, format: ReportFormat) -> anyhow::Result<()> {
+        let mut stdout = io::stdout();
+        match format {
+            ReportFormat::Json => {
+                serde_json::to_writer_pretty(&mut stdout, self)?;
+                writeln!(stdout)?;
+            }
+            ReportFormat::Human => {
+                for check in &self.checks {
+                    writeln!(
+                        stdout,
+                        "[{:7}] {}: {}",
+                        check.status, check.name, check.message
+                    )?;
+                }
+                let summary = if self.passed { "passed" } else { "failed" };
+                writeln!(stdout, "Runtime {} tests {summary}", self.runtime)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks if GPU used by the runtime can be detected.
+pub(crate) fn gpu<CONFIG: RuntimeConfig>(config: &CONFIG) -> anyhow::Result<String> {
+    let gpu = gpu_detection(config)
+        .map_err(|err| anyhow::anyhow!("Unable to detect GPU. Error: {err}"))?;
+    Ok(gpu.model)
+}
+
+/// Checks if file is present next to exe-unit binary and can be executed.
+pub(crate) fn executable(file_name: impl AsRef<Path>) -> anyhow::Result<String> {
+    let file = find_file(&file_name)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        anyhow::bail!("{file:?} is not a file");
+    }
+    #[cfg(target_family = "unix")]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            anyhow::bail!("{file:?} is not executable");
+        }
+    }
+    Ok(format!("{}", file.display()))
+}
+
+/// Checks if runtime API port is not taken by other application.
+pub(crate) fn port_free(host: &str, port: u16) -> anyhow::Result<String> {
+    TcpListener::bind((host, port))
+        .map_err(|err| anyhow::anyhow!("Port {host}:{port} is not available. Err {err}"))?;
+    Ok(format!("{host}:{port}"))
+}
+
+/// Checks available disk space in `dir`. Fails when it is lower than `min_gib`.
+pub(crate) fn disk_space(dir: &Path, min_gib: Option<f32>) -> anyhow::Result<String> {
+    let available_gib = fs2::available_space(dir)? as f64 / 1024.0 / 1024.0 / 1024.0;
+    let message = format!("{available_gib:.2} GiB available in {}", dir.display());
+    match min_gib {
+        Some(min_gib) if available_gib < min_gib as f64 => {
+            anyhow::bail!("{message}, required {min_gib:.2} GiB")
+        }
+        _ => Ok(message),
+    }
+}
+
+/// Starts the runtime, sends single inference request through the proxy and stops the runtime.
+/// Request is checked by the runtime access policy, filters and timeouts like requestor calls.
+pub(crate) async fn smoke_test<RUNTIME: Runtime>(
+    config: &RUNTIME::CONFIG,
+    model: Option<PathBuf>,
+) -> anyhow::Result<String> {
+    let started = Instant::now();
+    let client = reqwest::Client::new();
+    let api_url = config.api_url();
+    let mut proxy = Proxy::new::<RUNTIME>(
+        GsbToHttpProxy::new(api_url.clone()),
+        Suspension::default(),
+        RequestCounters::new(&config.endpoints())?,
+        Rc::new(RequestQueue::new(config.queue())?),
+        config,
+        Path::new("."),
+        Metrics::default(),
+    )?;
+    let (ready, latency) = with_runtime::<RUNTIME, _>(config, model, async {
+        wait_for_api(&client, &api_url).await?;
+        let ready = started.elapsed();
+        let request = RUNTIME::inference_request(config);
+        let latency = send_proxy_inference(&mut proxy, &request).await?;
+        Ok((ready, latency))
+    })
+    .await?;
+    Ok(format!(
+        "Runtime ready in {:.1}s. Inference latency {:.3}s",
+        ready.as_secs_f64(),
+        latency.as_secs_f64()
+    ))
+}
+
+/// Starts the runtime, awaits `task` and stops the runtime.
+pub(crate) async fn with_runtime<RUNTIME: Runtime, T>(
+    config: &RUNTIME::CONFIG,
+    model: Option<PathBuf>,
+    task: impl Future<Output = anyhow::Result<T>>,
+) -> anyhow::Result<T> {
+    let mut runtime = RUNTIME::start(model, config.clone(), ProcessContext::default()).await?;
+    let result = task.await;
+    if let Err(err) = runtime.stop().await {
+        log::warn!("Failed to stop runtime. Err {err}");
+    }
+    if tokio::time::timeout(STOP_TIMEOUT, runtime.wait())
+        .await
+        .is_err()
+    {
+        log::warn!("Runtime has not stopped in {STOP_TIMEOUT:?}. Killing it.");
+    }
+    result
+}
+
+/// Waits until runtime API responds.
+pub(crate) async fn wait_for_api(client: &reqwest::Client, api_url: &str) -> anyhow::Result<()> {
+    let deadline = Instant::now() + API_READY_TIMEOUT;
+    while let Err(err) = client.get(api_url).send().await {
+        if Instant::now() > deadline {
+            anyhow::bail!("Runtime API {api_url} not ready in {API_READY_TIMEOUT:?}. Err {err}");
+        }
+        tokio::time::sleep(API_READY_POLL_DELAY).await;
+    }
+    Ok(())
+}
+
+/// Sends inference request through the proxy and returns its latency.
+async fn send_proxy_inference(
+    proxy: &mut Proxy,
+    request: &InferenceRequest,
+) -> anyhow::Result<Duration> {
+    log::info!(
+        "Sending inference request through proxy: POST /{}",
+        request.path
+    );
+    let message = GsbHttpCallMessage {
+        method: "POST".into(),
+        path: format!("/{}", request.path),
+        body: Some(serde_json::to_vec(&request.body)?),
+        headers: HashMap::from([("Content-Type".into(), vec!["application/json".into()])]),
+    };
+    let sent = Instant::now();
+    let response = proxy
+        .call(message)
+        .await
+        .map_err(|err| anyhow::anyhow!("Inference request failed. Err {err}"))?;
+    let latency = sent.elapsed();
+    let status = response.header.status_code;
+    if !(200..300).contains(&status) {
+        anyhow::bail!("Inference request failed with status {status}");
+    }
+    Ok(latency)
+}
+
+/// Sends inference request and returns its latency.
+pub(crate) async fn send_inference(
+    client: &reqwest::Client,
+    api_url: &str,
+    request: &InferenceRequest,
+) -> anyhow::Result<Duration> {
+    let url = format!("{api_url}{}", request.path);
+    log::info!("Sending inference request: POST {url}");
+    let sent = Instant::now();
+    let response = client
+        .post(&url)
+        .json(&request.body)
+        .timeout(INFERENCE_TIMEOUT)
+        .send()
+        .await?;
+    let status = response.status();
+    response.bytes().await?;
+    let latency = sent.elapsed();
+    if !status.is_success() {
+        anyhow::bail!("Inference request failed with status {status}");
+    }
+    Ok(latency)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn warning_check_test() {
+        let mut report = TestReport::new("dummy");
+        report.check_warning("api-port", || anyhow::bail!("Port is not available"));
+        assert!(report.passed);
+        assert_eq!(report.checks[0].status, CheckStatus::Warning);
+
+        report.check("gpu", || anyhow::bail!("No GPU"));
+        assert!(!report.passed);
+    }
+}
@@ -0,0 +1,40 @@
+use std::net::TcpListener;
+
+use assert_cmd::Command;
+use predicates::prelude::*;
+
+/// Port not used by other tests running in parallel.
+fn free_port() -> u16 {
+    let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// Builds `dummy` binary next to `ya-runtime-ai`.
+/// `cargo test` builds binaries of packages with integration tests only.
+fn build_dummy() {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".into());
+    let mut build = std::process::Command::new(cargo);
+    build.args(["build", "-p", "dummy"]);
+    if !cfg!(debug_assertions) {
+        build.arg("--release");
+    }
+    assert!(build.status().unwrap().success(), "Failed to build dummy");
+}
+
+#[test]
+fn dummy_deep_test() {
+    build_dummy();
+    let runtime_config = format!(r#"{{"api_port": {}}}"#, free_port());
+    Command::cargo_bin("ya-runtime-ai")
+        .unwrap()
+        .env(
+            "EXE_UNIT_LOG_DIR",
+            std::env::temp_dir().join("ya-runtime-ai"),
+        )
+        .args(["--runtime", "dummy", "--runtime-config", &runtime_config])
+        .args(["test", "--deep", "--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"smoke-test\""))
+        .stdout(predicate::str::contains("\"failed\"").not());
+}